@@ -1,11 +1,17 @@
-use std::{borrow::BorrowMut, error::Error, fs::File, io::BufWriter, mem, sync::mpsc::Sender};
-
+use std::{error::Error, mem, sync::mpsc::Sender};
+
+use fqn_renamer::{
+    backup::BackupSet,
+    event_log::EventLog,
+    mapping::Mapping,
+    matched_file::MatchedFile,
+    rename::RenamePlan,
+    search::Search,
+    search_backend::{SearchBackend, SearchStats, SearchWorker},
+};
 use tui::{interactive_form::InteractiveForm, widgets::TextInputState};
 
-use crate::{
-    controller::AppEvent, event_log::EventLog, fqcn::Fqcn,
-    fqcn_processor::process_matched_file_fqcn, matched_file::MatchedFile, rg_worker::RgWorker,
-};
+use crate::controller::AppEvent;
 
 #[tui::macros::interactive_form]
 pub struct Inputs {
@@ -21,12 +27,12 @@ pub struct Inputs {
 
 pub enum SearchState {
     Idle,
-    SearchingFqcn(Fqcn),
-    SearchingIdent,
+    Searching(Search),
 }
 
 pub struct App {
     pub base_dir: String,
+    backend: Box<dyn SearchBackend>,
     pub inputs: Inputs,
     pub show_events: bool,
     pub events: EventLog,
@@ -36,15 +42,29 @@ pub struct App {
     pub results_scroll_offset: usize,
 
     pub found_matches: Vec<MatchedFile>,
-    pub replacments: Vec<MatchedFile>,
-
-    workers: Vec<RgWorker>,
+    pub plan: RenamePlan,
+    // how much the last search went through, once it's done
+    pub search_stats: Option<SearchStats>,
+    // the errors the last search carried on past
+    pub search_errors: Vec<String>,
+
+    // the pairs of a batch rename, all searched for at once
+    pub batch: Vec<Mapping>,
+    // how many files reference each pair, once the search is done
+    pub batch_references: Vec<usize>,
+
+    workers: Vec<Box<dyn SearchWorker>>,
 }
 
 impl App {
-    pub fn new(base_dir: String, events_sender: Sender<AppEvent>) -> App {
+    pub fn new(
+        base_dir: String,
+        backend: Box<dyn SearchBackend>,
+        events_sender: Sender<AppEvent>,
+    ) -> App {
         let mut ret = App {
             base_dir,
+            backend,
             search_state: SearchState::Idle,
             events_sender,
             show_events: false,
@@ -52,7 +72,11 @@ impl App {
             inputs: Default::default(),
             results_scroll_offset: 0,
             found_matches: vec![],
-            replacments: vec![],
+            plan: Default::default(),
+            search_stats: None,
+            search_errors: vec![],
+            batch: vec![],
+            batch_references: vec![],
             workers: vec![],
         };
         ret.inputs.focus_input(0);
@@ -62,43 +86,41 @@ impl App {
     }
 
     pub fn search_worker_finished(&mut self) {
-        let mut results_changed = false;
-
-        if matches!(self.search_state, SearchState::SearchingIdent) {
+        if let SearchState::Searching(search) = &self.search_state {
             for worker in self.workers.iter() {
-                let mut results = worker.results();
+                let results = mem::take(&mut *worker.results());
                 self.events.info(format!(
-                    "app: got {} matches from ident worker",
-                    results.len()
+                    "app: got {} matches from {} worker",
+                    results.len(),
+                    search.name()
                 ));
-                self.found_matches.append(results.borrow_mut());
-                results_changed = true;
-            }
-        } else if let SearchState::SearchingFqcn(fqcn) = &self.search_state {
-            for worker in self.workers.iter() {
-                let results = mem::take(&mut *worker.results());
-                self.events
-                    .info(format!("app: got {} matches from worker", results.len()));
-                let mut results = process_matched_file_fqcn(fqcn, results);
-                if !results.is_empty() {
-                    results_changed = true;
-                }
+                let mut results = search.process(results);
                 self.found_matches.append(&mut results);
             }
         }
 
         if self.workers.iter_mut().all(|worker| worker.finished()) {
+            let finished = mem::replace(&mut self.search_state, SearchState::Idle);
             if let Err(e) = self.kill_workers() {
                 self.log_error("Error killing workers")(e);
             }
             self.set_idle();
-        }
 
-        if results_changed {
-            self.update_replacements();
+            // planning reads every matched file, so it waits for all of them
+            if let SearchState::Searching(search) = finished {
+                if self.batch.is_empty() {
+                    self.update_replacements();
+                } else {
+                    self.batch_search_finished(&search);
+                }
+            }
         }
     }
 
+    pub fn is_searching(&self) -> bool {
+        !matches!(self.search_state, SearchState::Idle)
+    }
+
     pub fn search_input_submitted(&mut self) {
         if matches!(self.search_state, SearchState::Idle) {
             self.search_button_submitted();
@@ -109,193 +131,168 @@ impl App {
         if !matches!(self.search_state, SearchState::Idle) {
             self.events
                 .error("app: cannot do replace while searching".to_owned());
+            return;
         }
 
-        if let Err(e) = self.execute_replacements() {
-            self.events.error(format!("app: error replacing: {}", e));
+        // offered after the files changed under the last search
+        if self.inputs.replace_button.get_value() == "Search Again" {
+            self.inputs.replace_button.set_value("Replace");
+            match mem::take(&mut self.batch) {
+                batch if batch.is_empty() => self.search_button_submitted(),
+                batch => self.start_batch(batch),
+            }
+            return;
         }
-    }
-
-    fn execute_replacements(&mut self) -> Result<(), Box<dyn Error>> {
-        let replacements = mem::take(&mut self.replacments);
-        let mut num_replacements = 0;
 
-        for replacement in replacements.iter() {
-            num_replacements += self.execute_replacement(replacement)?;
+        let changed = self.plan.changed_files();
+        if let Some(path) = changed.first() {
+            self.events.error(format!(
+                "app: {} files changed since the search (first: {}), replace again to search again",
+                changed.len(),
+                path
+            ));
+            self.inputs.replace_button.set_value("Search Again");
+            return;
         }
 
-        self.replacments = replacements;
-        self.events.info(format!(
-            "app: replaced {} matches in {} files",
-            num_replacements,
-            self.replacments.len(),
-        ));
-
-        Ok(())
+        if let Err(e) = self.plan.apply(&self.base_dir, &mut self.events) {
+            self.events.error(format!("app: error replacing: {}", e));
+        }
     }
 
-    fn execute_replacement(&mut self, replacement: &MatchedFile) -> Result<usize, Box<dyn Error>> {
-        // copy the original file into a .bak version
-        let file_path = replacement.file_path();
-        let backup_file_path = format!("{}{}", file_path, ".bak");
-
-        if std::fs::metadata(&backup_file_path).is_ok() {
-            Err(format!("{} already exists, aborting", backup_file_path))?;
+    pub fn undo_submitted(&mut self) {
+        if !matches!(self.search_state, SearchState::Idle) {
+            self.events
+                .error("app: cannot undo while searching".to_owned());
+            return;
         }
 
-        std::fs::copy(file_path, backup_file_path)?;
-
-        let mut contents = ropey::Rope::from_reader(File::open(file_path)?)?;
-
-        let mut num_replacements = 0;
-
-        for line in replacement.lines() {
-            num_replacements += line.num_submatches();
-
-            let start_idx = contents.line_to_char(line.num());
-            let end_idx = contents.line_to_char(line.num() + 1);
-
-            contents.remove(start_idx..end_idx);
-            contents.insert(start_idx, line.value());
+        let undone = BackupSet::latest(&self.base_dir).and_then(|backups| match backups {
+            Some(backups) => backups.undo(&mut self.events),
+            None => Err("no rename to undo".into()),
+        });
+        match undone {
+            // what was found before no longer matches the files
+            Ok(_) => self.search_button_submitted(),
+            Err(e) => self.events.error(format!("app: error undoing: {}", e)),
         }
-
-        self.events.info(format!(
-            "app: {} replacements in {}",
-            num_replacements, file_path
-        ));
-
-        contents.write_to(BufWriter::new(File::create(file_path)?))?;
-
-        Ok(num_replacements)
     }
 
     pub fn update_replacements(&mut self) {
-        self.replacments.clear();
-
-        let find_ident = self.inputs.search_for_ident.get_value();
-        let repl_ident = self.inputs.replace_with_ident.get_value();
-
-        if let Some(find_fqcn) = Fqcn::new(find_ident) {
-            if let Some(repl_fqcn) = Fqcn::new(repl_ident) {
-                self.update_replacements_fqcn(find_fqcn, repl_fqcn);
-                return;
-            } else {
-                self.update_replacements_fqcn(find_fqcn.clone(), find_fqcn);
-                return;
-            }
-        }
-
-        // not a valid fqcn, just do a straight identifier replacement
-        let ident = if repl_ident.is_empty() {
-            find_ident
-        } else {
-            repl_ident
-        };
-        for mf in self.found_matches.iter() {
-            self.replacments.push(mf.replace(|_| ident));
+        // the plan of a batch is made once its search finishes
+        if !self.batch.is_empty() {
+            return;
         }
-    }
 
-    fn update_replacements_fqcn(&mut self, find: Fqcn, repl: Fqcn) {
-        for mf in self.found_matches.iter() {
-            self.replacments.push(mf.replace(|ident| {
-                if ident == find.ident() {
-                    repl.ident()
-                } else if ident == find.value() {
-                    repl.value()
-                } else if ident == find.package() {
-                    repl.package()
-                } else {
-                    unreachable!()
-                }
-            }));
-        }
+        self.plan = RenamePlan::new(
+            self.inputs.search_for_ident.get_value(),
+            self.inputs.replace_with_ident.get_value(),
+            &self.found_matches,
+        );
     }
 
     pub fn search_button_submitted(&mut self) {
         match self.search_state {
             SearchState::Idle => {
-                // try parsing fqcn
-                if let Some(fqcn) = Fqcn::new(self.inputs.search_for_ident.get_value()) {
-                    self.set_searching_and_clear_results();
-                    self.search_for_fqcn(fqcn);
-                } else {
-                    self.set_searching_and_clear_results();
-                    self.search_state = SearchState::SearchingIdent;
-                    self.search_for_raw_ident(self.inputs.search_for_ident.get_value().to_owned());
-                }
+                self.batch.clear();
+                let search = Search::new(self.inputs.search_for_ident.get_value());
+                self.set_searching_and_clear_results();
+                self.start_search(search);
             }
 
-            SearchState::SearchingFqcn(_) | SearchState::SearchingIdent => {
+            SearchState::Searching(_) => {
                 if let Err(e) = self.kill_workers() {
                     self.log_error("error stopping search")(e);
                 }
 
+                if self.batch.is_empty() {
+                    // what was found so far
+                    self.update_replacements();
+                } else {
+                    // nothing to split the results of a stopped batch by
+                    self.events.info("app: stopped batch".to_owned());
+                    self.batch.clear();
+                    self.found_matches.clear();
+                }
                 self.set_idle();
             }
         }
     }
 
-    fn search_for_fqcn(&mut self, fqcn: Fqcn) {
-        // find all files that reference the entire FQCN
-        let fqcn_worker = RgWorker::new(
-            self.events_sender.clone(),
-            "fqcn_worker",
-            self.events.clone(),
-            &[
-                // ignore all .bak files
-                "-g",
-                "!*.bak",
-                "--json",
-                "-C2",
-                // find the thing that defines the package, references the
-                // identifier (filter out the false positives later),
-                // or imports the identifier (use that for filtering)
-                &format!(
-                    r"(^package {};?$)|(\b{}\b)|(\b{}\b)|(^import {};?$)",
-                    // `package foo.Bar`
-                    fqcn.package(),
-                    // `Bar`
-                    fqcn.ident(),
-                    // `foo.Bar`
-                    fqcn.value(),
-                    // `import foo.Bar`
-                    fqcn.value()
-                ),
-                &self.base_dir,
-            ],
-        );
-
-        if let Err(err) = fqcn_worker {
-            self.log_error("Error starting `rg` (fqcn)")(err);
-            return;
+    pub fn start_batch(&mut self, batch: Vec<Mapping>) {
+        let (skipped, batch): (Vec<_>, Vec<_>) = batch
+            .into_iter()
+            .partition(|mapping| mapping.unsupported().is_some());
+        for mapping in skipped.iter() {
+            self.events.error(format!(
+                "app: skipping {} -> {}: {}",
+                mapping.find,
+                mapping.replace,
+                mapping.unsupported().unwrap_or_default()
+            ));
         }
-
-        self.search_state = SearchState::SearchingFqcn(fqcn);
-        let worker = fqcn_worker.unwrap();
-        let pid = worker.pid();
-        self.workers.push(worker);
-        self.events.info(format!("start `rg` (fqcn): {}", pid));
+        self.events
+            .info(format!("app: starting batch of {} renames", batch.len()));
+        let search = Search::for_batch(&batch);
+        self.batch = batch;
+        self.batch_references.clear();
+        self.plan.clear();
+        self.set_searching_and_clear_results();
+        self.start_search(search);
     }
 
-    fn search_for_raw_ident(&mut self, ident: String) {
-        let rg_worker = RgWorker::new(
-            self.events_sender.clone(),
-            "ident",
-            self.events.clone(),
-            &["--json", "-C1", &format!("\\b{}\\b", ident), &self.base_dir],
-        );
+    // splits what the search found between the pairs of the batch, and plans
+    // them all against the original files
+    fn batch_search_finished(&mut self, search: &Search) {
+        let found = search.split(mem::take(&mut self.found_matches));
+        self.batch_references = found.iter().map(Vec::len).collect();
+        self.events.info(format!(
+            "app: finished batch of {} renames",
+            self.batch.len()
+        ));
+        let num_referenced = self.batch_references.iter().filter(|&&n| n > 0).count();
+        self.events.info(format!(
+            "app: {} of {} mappings are referenced",
+            num_referenced,
+            self.batch.len()
+        ));
 
-        if let Err(err) = rg_worker {
-            self.log_error("Error starting `rg`")(err);
+        self.plan = RenamePlan::new_batch(self.batch.iter().zip(found.iter().map(Vec::as_slice)));
+        for conflict in self.plan.conflicts.iter() {
+            self.events.error(format!("app: conflict: {}", conflict));
+        }
+        self.found_matches = found.into_iter().flatten().collect();
+    }
+
+    fn start_search(&mut self, search: Search) {
+        let events_sender = self.events_sender.clone();
+        let backend = self.backend.as_ref();
+        let worker = search.spawn(backend, &self.base_dir, self.events.clone(), move || {
+            // the app may have already shut down
+            let _ = events_sender.send(AppEvent::WorkerUpdate);
+        });
+
+        if let Err(err) = worker {
+            self.log_error(&format!(
+                "Error starting {} search ({})",
+                backend.name(),
+                search.name()
+            ))(err);
+            // with no search to finish, the batch would never be planned
+            if !self.batch.is_empty() {
+                self.events.error("app: stopped batch".to_owned());
+                self.batch.clear();
+                self.batch_references.clear();
+            }
+            self.set_idle();
             return;
         }
 
-        self.search_state = SearchState::SearchingIdent;
-        let worker = rg_worker.unwrap();
-        let pid = worker.pid();
+        let worker = worker.unwrap();
+        self.events
+            .info(format!("start {} ({})", backend.name(), search.name()));
         self.workers.push(worker);
-        self.events.info(format!("start `rg` ident: {}", pid));
+        self.search_state = SearchState::Searching(search);
     }
 
     fn log_error(&self, message: &str) -> impl FnMut(Box<dyn Error>) -> Box<dyn Error> {
@@ -318,6 +315,11 @@ impl App {
             worker
                 .kill_and_wait()
                 .map_err(self.log_error("error killing worker"))?;
+            // once it has exited, so that its exit status is among the errors
+            if let Some(stats) = worker.stats() {
+                *self.search_stats.get_or_insert_with(Default::default) += stats;
+            }
+            self.search_errors.extend(worker.errors());
         }
         self.events.info("cleared workers".to_string());
         Ok(())
@@ -334,7 +336,10 @@ impl App {
             self.inputs.search_for_ident.get_value()
         ));
         self.inputs.search_button.set_value("Stop Search");
+        self.inputs.replace_button.set_value("Replace");
         self.found_matches.clear();
+        self.search_stats = None;
+        self.search_errors.clear();
         self.results_scroll_offset = 0;
     }
 }
@@ -0,0 +1,317 @@
+use std::{
+    error::Error,
+    path::{Component, Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    event_log::EventLog,
+    journal::{self, Journal, JournalFile},
+    matched_file::file_hash,
+    paths::absolute,
+    rename::FileMove,
+};
+
+/// Where backup sets are kept, relative to the directory a rename searched.
+/// The paths in them are absolute, so a rename can be undone from anywhere.
+pub const BACKUPS_DIR: &str = ".fqn-renamer/backups";
+
+const MANIFEST: &str = "manifest.json";
+
+/// Copies of the files that one rename changed, from before it changed them.
+///
+/// Each set is a directory of its own under [`BACKUPS_DIR`] in the searched
+/// directory, named after
+/// when the rename was made, with the copies under `files/` and the rename's
+/// [`Journal`] as `manifest.json`.
+#[derive(Debug)]
+pub struct BackupSet {
+    // the directory the rename searched
+    base_dir: String,
+    dir: PathBuf,
+    // `None` if the manifest is missing, e.g. if the rename was interrupted
+    journal: Option<Journal>,
+}
+
+impl BackupSet {
+    /// Starts a new, empty backup set in `base_dir`, with a manifest of
+    /// nothing yet.
+    pub fn create(base_dir: &str) -> Result<BackupSet, Box<dyn Error>> {
+        let secs = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let name = timestamp(secs);
+        let backups_dir = backups_dir(base_dir);
+        std::fs::create_dir_all(&backups_dir)
+            .map_err(|err| format!("{}: {}", backups_dir.display(), err))?;
+
+        // renames made within the same second
+        let mut dir = backups_dir.join(&name);
+        let mut num = 0;
+        while let Err(err) = std::fs::create_dir(&dir) {
+            if err.kind() != std::io::ErrorKind::AlreadyExists {
+                Err(format!("{}: {}", dir.display(), err))?;
+            }
+            num += 1;
+            dir = backups_dir.join(format!("{}-{}", name, num));
+        }
+
+        let backups = BackupSet {
+            base_dir: base_dir.to_owned(),
+            dir,
+            journal: Some(Journal {
+                version: journal::VERSION,
+                files: vec![],
+                moves: vec![],
+                dirs: vec![],
+            }),
+        };
+        backups.write_manifest()?;
+        Ok(backups)
+    }
+
+    /// Every backup set in `base_dir`, oldest first.
+    pub fn list(base_dir: &str) -> Result<Vec<BackupSet>, Box<dyn Error>> {
+        let backups_dir = backups_dir(base_dir);
+        let entries = match std::fs::read_dir(&backups_dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => Err(format!("{}: {}", backups_dir.display(), err))?,
+        };
+
+        let mut sets = vec![];
+        for entry in entries {
+            let dir = entry?.path();
+            if dir.is_dir() {
+                let journal = Journal::read(&dir.join(MANIFEST)).ok();
+                sets.push(BackupSet {
+                    base_dir: base_dir.to_owned(),
+                    dir,
+                    journal,
+                });
+            }
+        }
+        sets.sort_by(|a, b| a.dir.cmp(&b.dir));
+        Ok(sets)
+    }
+
+    /// The backup set of the last rename in `base_dir` that hasn't been
+    /// undone. Sets
+    /// without a manifest that hold no backups either are skipped, there's
+    /// nothing in them to undo; one that does hold backups is returned, for
+    /// [`BackupSet::undo`] to report.
+    pub fn latest(base_dir: &str) -> Result<Option<BackupSet>, Box<dyn Error>> {
+        Ok(Self::list(base_dir)?
+            .into_iter()
+            .rev()
+            .find(|set| set.journal.is_some() || set.dir.join("files").exists()))
+    }
+
+    /// Removes all but the `keep` newest backup sets in `base_dir`,
+    /// returning the removed ones.
+    pub fn prune(base_dir: &str, keep: usize) -> Result<Vec<BackupSet>, Box<dyn Error>> {
+        let mut sets = Self::list(base_dir)?;
+        let pruned = sets
+            .drain(..sets.len().saturating_sub(keep))
+            .collect::<Vec<_>>();
+        for set in pruned.iter() {
+            std::fs::remove_dir_all(&set.dir)?;
+        }
+        Ok(pruned)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn name(&self) -> String {
+        self.dir
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    pub fn journal(&self) -> Option<&Journal> {
+        self.journal.as_ref()
+    }
+
+    /// Copies the file at `file_path` into the set, and records it in the
+    /// manifest, so that it can be restored by hand even if the rename is
+    /// interrupted.
+    pub fn back_up(&mut self, file_path: &str) -> Result<(), Box<dyn Error>> {
+        // the same path under `files/`, as far as it can be
+        let relative = Path::new(file_path)
+            .components()
+            .filter(|component| matches!(component, Component::Normal(_)))
+            .collect::<PathBuf>();
+        let backup = self.dir.join("files").join(relative);
+        if std::fs::metadata(&backup).is_ok() {
+            Err(format!("{} already exists, aborting", backup.display()))?;
+        }
+        if let Some(parent) = backup.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::copy(file_path, &backup)?;
+
+        let journal = self.journal.as_mut().ok_or("backup set has no manifest")?;
+        journal.files.push(JournalFile {
+            path: absolute(file_path).to_string_lossy().into_owned(),
+            backup: backup.to_string_lossy().into_owned(),
+            sha256: String::new(),
+        });
+        self.write_manifest()
+    }
+
+    /// Records the moves that followed the backups, the directories created
+    /// for them, and the hash of each file as the rename left it, so that
+    /// undo can tell if they've changed since.
+    pub fn finish(&mut self, moves: &[FileMove], dirs: &[PathBuf]) -> Result<(), Box<dyn Error>> {
+        let journal = self.journal.as_mut().ok_or("backup set has no manifest")?;
+        journal.moves = moves
+            .iter()
+            .map(|file_move| FileMove {
+                from: absolute(&file_move.from).to_string_lossy().into_owned(),
+                to: absolute(&file_move.to).to_string_lossy().into_owned(),
+            })
+            .collect();
+        journal.dirs = dirs
+            .iter()
+            .map(|dir| absolute(dir).to_string_lossy().into_owned())
+            .collect();
+        for idx in 0..journal.files.len() {
+            let path = journal.moved_to(&journal.files[idx].path).to_owned();
+            journal.files[idx].sha256 = file_hash(&path)?;
+        }
+        self.write_manifest()
+    }
+
+    /// Undoes the rename, see [`Journal::undo`], and removes the set.
+    pub fn undo(self, events: &mut EventLog) -> Result<usize, Box<dyn Error>> {
+        let journal = self.journal.as_ref().ok_or_else(|| {
+            format!(
+                "{} has no manifest, restore its files by hand",
+                self.dir.display()
+            )
+        })?;
+        let num_files = journal.undo(&self.base_dir, events)?;
+        self.remove()?;
+        Ok(num_files)
+    }
+
+    pub fn remove(self) -> Result<(), Box<dyn Error>> {
+        std::fs::remove_dir_all(&self.dir)?;
+        Ok(())
+    }
+
+    fn write_manifest(&self) -> Result<(), Box<dyn Error>> {
+        match &self.journal {
+            Some(journal) => journal.write(&self.dir.join(MANIFEST)),
+            None => Ok(()),
+        }
+    }
+}
+
+// absolute, so that the paths of the backups in the manifest are too
+fn backups_dir(base_dir: &str) -> PathBuf {
+    absolute(base_dir).join(BACKUPS_DIR)
+}
+
+// `2022-08-08T23-06-40Z` for `secs` since the unix epoch, in UTC; sorts in
+// the same order as the times do, and is safe to use as a file name
+fn timestamp(secs: u64) -> String {
+    let (days, secs) = ((secs / 86400) as i64, secs % 86400);
+
+    // the civil date of a day number, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}-{:02}-{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use crate::journal::Journal;
+
+    use super::{timestamp, BackupSet, BACKUPS_DIR, MANIFEST};
+
+    #[test]
+    fn test_create_writes_manifest() {
+        let dir = std::env::temp_dir().join(format!("fqn-renamer-backup-{}", std::process::id()));
+        let base_dir = dir.to_str().unwrap();
+        std::fs::create_dir_all(base_dir).unwrap();
+
+        let backups = BackupSet::create(base_dir).unwrap();
+        assert!(backups.dir().starts_with(dir.join(BACKUPS_DIR)));
+        let journal = Journal::read(&backups.dir().join(MANIFEST)).unwrap();
+        assert!(journal.files.is_empty());
+        backups.remove().unwrap();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_list_and_prune() {
+        let dir = std::env::temp_dir().join(format!("fqn-renamer-prune-{}", std::process::id()));
+        let base_dir = dir.to_str().unwrap();
+        std::fs::create_dir_all(base_dir).unwrap();
+        assert!(BackupSet::list(base_dir).unwrap().is_empty());
+
+        let names = (0..3)
+            .map(|_| BackupSet::create(base_dir).unwrap().name())
+            .collect::<Vec<_>>();
+        // an interrupted rename, that didn't get as far as any backups
+        std::fs::remove_file(dir.join(BACKUPS_DIR).join(&names[2]).join(MANIFEST)).unwrap();
+
+        let sets = BackupSet::list(base_dir).unwrap();
+        assert_eq!(names, sets.iter().map(BackupSet::name).collect::<Vec<_>>());
+        assert!(sets[2].journal().is_none());
+        assert_eq!(
+            Some(names[1].clone()),
+            BackupSet::latest(base_dir).unwrap().map(|set| set.name())
+        );
+
+        let pruned = BackupSet::prune(base_dir, 1).unwrap();
+        assert_eq!(
+            names[..2],
+            pruned.iter().map(BackupSet::name).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            names[2..],
+            BackupSet::list(base_dir)
+                .unwrap()
+                .iter()
+                .map(BackupSet::name)
+                .collect::<Vec<_>>()
+        );
+        assert!(BackupSet::latest(base_dir).unwrap().is_none());
+        assert!(BackupSet::prune(base_dir, 1).unwrap().is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_timestamp() {
+        assert_eq!("1970-01-01T00-00-00Z", timestamp(0));
+        assert_eq!("2022-08-08T23-06-40Z", timestamp(1660000000));
+        assert_eq!("2000-02-29T23-59-59Z", timestamp(951868799));
+    }
+}
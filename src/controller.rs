@@ -140,6 +140,12 @@ fn handle_crossterm_event(
                 app.show_events = !app.show_events;
             }
 
+            // undo the last rename
+            KeyEvent {
+                code: KeyCode::Char('z'),
+                modifiers: KeyModifiers::CONTROL,
+            } => app.undo_submitted(),
+
             // quit the app
             KeyEvent {
                 code: KeyCode::Char('q'),
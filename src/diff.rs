@@ -0,0 +1,512 @@
+use std::{
+    borrow::Cow,
+    error::Error,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use itertools::Itertools;
+
+use crate::{
+    matched_file::{LineKind, MatchedFile},
+    paths::absolute,
+    rename::RenamePlan,
+    text::{read_text, strip_bom, Encoding, BOM},
+};
+
+// one line of a file's diff
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum DiffLine<'a> {
+    Context(Cow<'a, str>),
+    Removed(Cow<'a, str>),
+    Added(Cow<'a, str>),
+}
+
+impl<'a> DiffLine<'a> {
+    fn text(&self) -> &str {
+        match self {
+            DiffLine::Context(text) | DiffLine::Removed(text) | DiffLine::Added(text) => text,
+        }
+    }
+
+    fn with_bom(&self) -> DiffLine<'a> {
+        let text = Cow::Owned(format!("{}{}", BOM, self.text()));
+        match self {
+            DiffLine::Context(_) => DiffLine::Context(text),
+            DiffLine::Removed(_) => DiffLine::Removed(text),
+            DiffLine::Added(_) => DiffLine::Added(text),
+        }
+    }
+
+    fn is_change(&self) -> bool {
+        !matches!(self, DiffLine::Context(_))
+    }
+
+    fn in_old(&self) -> bool {
+        !matches!(self, DiffLine::Added(_))
+    }
+
+    fn in_new(&self) -> bool {
+        !matches!(self, DiffLine::Removed(_))
+    }
+}
+
+/// The changes `plan` would make, as a unified diff with `context` lines
+/// around each change that `git apply` or `patch -p1` can apply. Moved files
+/// get git's rename headers.
+///
+/// Paths in the diff are relative to the root of the git repository that
+/// `base_dir` is in, as `git apply` expects them, or to `base_dir` itself
+/// outside of one. The lines of each file are in its own encoding, so that
+/// the diff applies to Latin-1 files too.
+pub fn unified_diff(
+    plan: &RenamePlan,
+    base_dir: &str,
+    context: usize,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let root = patch_root(base_dir);
+    let mut diff = vec![];
+
+    for replacement in plan.replacements.iter() {
+        let from = replacement.file_path();
+        let (contents, encoding) = read_text(from).map_err(|err| format!("{}: {}", from, err))?;
+        let to = plan.moved_to(from).unwrap_or(from);
+        diff.extend(file_diff(
+            &contents,
+            encoding,
+            replacement,
+            to,
+            &root,
+            context,
+        )?);
+    }
+
+    // moves of files that aren't edited otherwise
+    for file_move in plan.moves.iter() {
+        if !plan
+            .replacements
+            .iter()
+            .any(|replacement| replacement.file_path() == file_move.from)
+        {
+            diff.extend(diff_header(&file_move.from, &file_move.to, &root).bytes());
+        }
+    }
+
+    Ok(diff)
+}
+
+// the diff of one file, moved to `to`, with paths relative to `root`; empty
+// if nothing changes. The headers are UTF-8, like the paths, and the hunks
+// are in the file's `encoding`
+fn file_diff(
+    contents: &str,
+    encoding: Encoding,
+    replacement: &MatchedFile,
+    to: &str,
+    root: &Path,
+    context: usize,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let from = replacement.file_path();
+    let lines = diff_lines(contents, replacement);
+    let hunks = hunks(&lines, context);
+    if hunks.is_empty() && from == to {
+        return Ok(vec![]);
+    }
+
+    let mut header = diff_header(from, to, root);
+    if hunks.is_empty() {
+        return Ok(header.into_bytes());
+    }
+
+    header += &format!(
+        "--- a/{}\n+++ b/{}\n",
+        patch_path(from, root),
+        patch_path(to, root)
+    );
+    let mut diff = String::new();
+    for hunk in hunks {
+        let old_before = lines[..hunk.start].iter().filter(|l| l.in_old()).count();
+        let new_before = lines[..hunk.start].iter().filter(|l| l.in_new()).count();
+        let old_len = lines[hunk.clone()].iter().filter(|l| l.in_old()).count();
+        let new_len = lines[hunk.clone()].iter().filter(|l| l.in_new()).count();
+        diff += &format!(
+            "@@ -{} +{} @@\n",
+            hunk_range(old_before, old_len),
+            hunk_range(new_before, new_len)
+        );
+
+        for line in lines[hunk].iter() {
+            let prefix = match line {
+                DiffLine::Context(_) => ' ',
+                DiffLine::Removed(_) => '-',
+                DiffLine::Added(_) => '+',
+            };
+            let text = line.text();
+            diff.push(prefix);
+            diff += text;
+            if !text.ends_with('\n') {
+                diff += "\n\\ No newline at end of file\n";
+            }
+        }
+    }
+
+    // only Latin-1 can fail, on names it has no characters for
+    let diff = encoding.encode(&diff).ok_or_else(|| {
+        format!(
+            "{}: the renamed lines don't fit the file's Latin-1 encoding",
+            from
+        )
+    })?;
+    Ok([header.as_bytes(), &diff].concat())
+}
+
+// every line of the file, with the planned lines in place of the originals
+fn diff_lines<'a>(contents: &'a str, replacement: &'a MatchedFile) -> Vec<DiffLine<'a>> {
+    let original = strip_bom(contents)
+        .split_inclusive('\n')
+        .collect::<Vec<_>>();
+    let mut planned = replacement.lines().peekable();
+    let mut lines = vec![];
+
+    // one past the last line, for lines inserted at the end of the file
+    for num in 0..=original.len() {
+        let mut value = original.get(num).copied();
+        let mut removed = false;
+
+        while let Some(line) = planned.next_if(|line| line.num() <= num) {
+            if line.num() < num {
+                continue;
+            }
+            match line.kind() {
+                LineKind::Insert => lines.push(DiffLine::Added(line.value().into())),
+                LineKind::Replace if Some(line.value()) != value => {
+                    removed = true;
+                    value = Some(line.value());
+                }
+                LineKind::Replace => {}
+                LineKind::Delete => {
+                    removed = true;
+                    value = None;
+                }
+            }
+        }
+
+        if let Some(&original) = original.get(num) {
+            if removed {
+                lines.push(DiffLine::Removed(original.into()));
+                if let Some(value) = value {
+                    lines.push(DiffLine::Added(value.into()));
+                }
+            } else {
+                lines.push(DiffLine::Context(original.into()));
+            }
+        }
+    }
+
+    if contents.starts_with(BOM) {
+        keep_bom(&mut lines);
+    }
+
+    // removed lines come before the added lines in each run of changes, as
+    // diff shows them
+    let mut start = 0;
+    while start < lines.len() {
+        let len = lines[start..]
+            .iter()
+            .take_while(|line| line.is_change())
+            .count();
+        lines[start..start + len].sort_by_key(|line| line.in_new());
+        start += len.max(1);
+    }
+
+    lines
+}
+
+// puts the byte order mark back in front of the first line of both the old
+// and the new file; the first line of the old file no longer is one of the
+// new file if a line was inserted in front of it, so it's removed and added
+// back without the mark
+fn keep_bom(lines: &mut Vec<DiffLine>) {
+    let first_old = lines.iter().position(|line| line.in_old());
+    if let Some(old) = first_old {
+        if let DiffLine::Context(text) = &lines[old] {
+            if lines[..old].iter().any(|line| line.in_new()) {
+                let text = text.clone();
+                lines[old] = DiffLine::Removed(text.clone());
+                lines.insert(old + 1, DiffLine::Added(text));
+            }
+        }
+    }
+
+    let first_new = lines.iter().position(|line| line.in_new());
+    for idx in [first_old, first_new].into_iter().flatten().dedup() {
+        lines[idx] = lines[idx].with_bom();
+    }
+}
+
+// the ranges of `lines` that make up each hunk, with `context` lines on
+// either side of the changes in it
+fn hunks(lines: &[DiffLine], context: usize) -> Vec<Range<usize>> {
+    let mut hunks: Vec<Range<usize>> = vec![];
+
+    for (idx, _) in lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| line.is_change())
+    {
+        let start = idx.saturating_sub(context);
+        let end = (idx + context + 1).min(lines.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.end => last.end = end,
+            _ => hunks.push(start..end),
+        }
+    }
+
+    hunks
+}
+
+// `start,len` of a hunk, after `before` lines; an empty hunk starts at the
+// line it follows
+fn hunk_range(before: usize, len: usize) -> String {
+    match len {
+        0 => format!("{},0", before),
+        1 => format!("{}", before + 1),
+        _ => format!("{},{}", before + 1, len),
+    }
+}
+
+fn diff_header(from: &str, to: &str, root: &Path) -> String {
+    let (from, to) = (patch_path(from, root), patch_path(to, root));
+    let mut header = format!("diff --git a/{} b/{}\n", from, to);
+    if from != to {
+        header += &format!("rename from {}\nrename to {}\n", from, to);
+    }
+    header
+}
+
+// the directory the diff is applied in: the root of the git repository
+// `base_dir` is in, or `base_dir` itself
+fn patch_root(base_dir: &str) -> PathBuf {
+    let base_dir = absolute(base_dir);
+    base_dir
+        .ancestors()
+        .find(|dir| dir.join(".git").exists())
+        .unwrap_or(&base_dir)
+        .to_owned()
+}
+
+// `path` relative to `root`, as the diff names it
+fn patch_path(path: &str, root: &Path) -> String {
+    match absolute(path).strip_prefix(root) {
+        Ok(relative) => relative.to_string_lossy().into_owned(),
+        Err(_) => path.trim_start_matches("./").to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use itertools::assert_equal;
+
+    use crate::matched_file::{Line, MatchedFile};
+
+    use std::path::Path;
+
+    use crate::{paths::absolute, text::Encoding};
+
+    use super::{file_diff, patch_path, patch_root};
+
+    const CONTENTS: &str = "package com.foo;\n\nimport a.A;\n\nclass Bar {\n  A a;\n}\n";
+
+    // the diff of a UTF-8 file, as text
+    fn text_diff(contents: &str, replacement: &MatchedFile, to: &str, context: usize) -> String {
+        let diff = file_diff(
+            contents,
+            Encoding::Utf8,
+            replacement,
+            to,
+            &absolute("."),
+            context,
+        );
+        String::from_utf8(diff.unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_file_diff() {
+        let replacement = MatchedFile::new(
+            "./com/foo/Bar.java",
+            vec![
+                Line::new(0, "package com.foo;\n", vec![8..15]).replace(|_| "net.baz"),
+                Line::new(2, "import a.A;\n", vec![]).delete(),
+                Line::inserted(3, "import b.B;\n"),
+                Line::new(4, "class Bar {\n", vec![6..9]).replace(|_| "Quux"),
+                Line::inserted(7, "// end\n"),
+            ],
+        );
+
+        assert_equal(
+            [
+                "diff --git a/com/foo/Bar.java b/net/baz/Quux.java",
+                "rename from com/foo/Bar.java",
+                "rename to net/baz/Quux.java",
+                "--- a/com/foo/Bar.java",
+                "+++ b/net/baz/Quux.java",
+                "@@ -1,7 +1,8 @@",
+                "-package com.foo;",
+                "+package net.baz;",
+                " ",
+                "-import a.A;",
+                "+import b.B;",
+                " ",
+                "-class Bar {",
+                "+class Quux {",
+                "   A a;",
+                " }",
+                "+// end",
+            ],
+            text_diff(CONTENTS, &replacement, "./net/baz/Quux.java", 3).lines(),
+        );
+    }
+
+    #[test]
+    fn test_hunks() {
+        let replacement = MatchedFile::new(
+            "com/foo/Bar.java",
+            vec![
+                Line::new(0, "package com.foo;\n", vec![8..15]).replace(|_| "com.foo"),
+                Line::new(4, "class Bar {\n", vec![6..9]).replace(|_| "Quux"),
+                Line::new(6, "}\n", vec![0..1]).replace(|_| "} // Quux"),
+            ],
+        );
+
+        assert_equal(
+            [
+                "diff --git a/com/foo/Bar.java b/com/foo/Bar.java",
+                "--- a/com/foo/Bar.java",
+                "+++ b/com/foo/Bar.java",
+                "@@ -5 +5 @@",
+                "-class Bar {",
+                "+class Quux {",
+                "@@ -7 +7 @@",
+                "-}",
+                "+} // Quux",
+            ],
+            text_diff(CONTENTS, &replacement, "com/foo/Bar.java", 0).lines(),
+        );
+
+        // a line without a newline at the end of the file
+        let replacement = MatchedFile::new(
+            "Bar.java",
+            vec![Line::new(0, "class Bar {}", vec![6..9]).replace(|_| "Quux")],
+        );
+        assert_equal(
+            [
+                "@@ -1 +1 @@",
+                "-class Bar {}",
+                "\\ No newline at end of file",
+                "+class Quux {}",
+                "\\ No newline at end of file",
+            ],
+            text_diff("class Bar {}", &replacement, "Bar.java", 3)
+                .lines()
+                .skip(3),
+        );
+    }
+
+    #[test]
+    fn test_bom() {
+        let contents = "\u{feff}class Bar {}\n";
+        let replacement = MatchedFile::new(
+            "Bar.java",
+            vec![Line::new(0, "class Bar {}\n", vec![6..9]).replace(|_| "Quux")],
+        );
+        assert_equal(
+            [
+                "@@ -1 +1 @@",
+                "-\u{feff}class Bar {}",
+                "+\u{feff}class Quux {}",
+            ],
+            text_diff(contents, &replacement, "Bar.java", 3)
+                .lines()
+                .skip(3),
+        );
+
+        // a line inserted in front of the first one takes the mark
+        let replacement = MatchedFile::new("Bar.java", vec![Line::inserted(0, "import a.A;\n")]);
+        assert_equal(
+            [
+                "@@ -1 +1,2 @@",
+                "-\u{feff}class Bar {}",
+                "+\u{feff}import a.A;",
+                "+class Bar {}",
+            ],
+            text_diff(contents, &replacement, "Bar.java", 3)
+                .lines()
+                .skip(3),
+        );
+    }
+
+    #[test]
+    fn test_latin1() {
+        let contents = "// caf\u{e9}\nclass Bar {}\n";
+        let replacement = MatchedFile::new(
+            "Bar.java",
+            vec![Line::new(1, "class Bar {}\n", vec![6..9]).replace(|_| "Quux")],
+        );
+        let diff = file_diff(
+            contents,
+            Encoding::Latin1,
+            &replacement,
+            "Bar.java",
+            &absolute("."),
+            3,
+        )
+        .unwrap();
+        assert!(diff.ends_with(b"@@ -1,2 +1,2 @@\n // caf\xe9\n-class Bar {}\n+class Quux {}\n"));
+
+        // a name that Latin-1 has no characters for
+        let replacement = MatchedFile::new(
+            "Bar.java",
+            vec![Line::new(1, "class Bar {}\n", vec![6..9]).replace(|_| "\u{411}ar")],
+        );
+        assert!(file_diff(
+            contents,
+            Encoding::Latin1,
+            &replacement,
+            "Bar.java",
+            &absolute("."),
+            3
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_patch_path() {
+        let root = Path::new("/tmp/clitest");
+        assert_eq!(
+            "com/foo/Bar.java",
+            patch_path("/tmp/clitest/com/foo/Bar.java", root)
+        );
+        assert_eq!(
+            "net/baz/Quux.java",
+            patch_path("/tmp/clitest/./net/baz/Quux.java", root)
+        );
+        assert_eq!("src/Bar.java", patch_path("./src/Bar.java", &absolute(".")));
+
+        // relative to the repository, wherever in it the search ran
+        let dir = std::env::temp_dir().join(format!("fqn-renamer-root-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join(".git")).unwrap();
+        std::fs::create_dir_all(dir.join("app/src")).unwrap();
+        assert_eq!(dir, patch_root(dir.join("app/src").to_str().unwrap()));
+        std::fs::remove_dir_all(&dir).unwrap();
+        let outside =
+            std::env::temp_dir().join(format!("fqn-renamer-outside-{}", std::process::id()));
+        std::fs::create_dir_all(&outside).unwrap();
+        if !std::env::temp_dir()
+            .ancestors()
+            .any(|dir| dir.join(".git").exists())
+        {
+            assert_eq!(outside, patch_root(outside.to_str().unwrap()));
+        }
+        std::fs::remove_dir_all(&outside).unwrap();
+    }
+}
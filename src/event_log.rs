@@ -46,7 +46,7 @@ impl EventLog {
         }
     }
 
-    pub fn list(&self) -> MappedMutexGuard<VecDeque<LogLine>> {
+    pub fn list(&self) -> MappedMutexGuard<'_, VecDeque<LogLine>> {
         MutexGuard::map(self.state.lock(), |state| &mut state.events)
     }
 }
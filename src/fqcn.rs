@@ -1,11 +1,13 @@
-use std::ops::Range;
+use std::{ops::Range, path::PathBuf};
 
 use regex::Regex;
 
+// a class name, possibly nested in outer classes, e.g. `foo.bar.Outer.Inner`
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Fqcn {
     value: String,
     package_range: Range<usize>,
+    class_range: Range<usize>,
     ident_range: Range<usize>,
 }
 
@@ -13,18 +15,44 @@ impl Fqcn {
     pub fn new<S: Into<String>>(value: S) -> Option<Self> {
         let value = value.into();
 
-        let re = Regex::new(r"^(([a-z0-9][a-z0-9\.]+)+\.)?([A-Z][\w]*)?$").unwrap();
+        let re =
+            Regex::new(r"^(([a-z0-9][a-z0-9\.]+)+\.)?(([A-Z][\w]*\.)*([A-Z][\w]*))?$").unwrap();
         let captures = re.captures(value.as_ref())?;
         let package_range = captures.get(2).map(|m| m.range())?;
-        let ident_range = captures.get(3).map(|m| m.range())?;
+        let class_range = captures.get(3).map(|m| m.range())?;
+        let ident_range = captures.get(5).map(|m| m.range())?;
 
         Some(Fqcn {
             value,
             package_range,
+            class_range,
             ident_range,
         })
     }
 
+    // a class from its binary name, e.g. `foo.bar.Outer$Inner`, whatever the
+    // case of its name; obfuscated names like `a.b` are classes too
+    pub fn from_binary(name: &str) -> Option<Self> {
+        let (package, class) = name.rsplit_once('.')?;
+        let re = Regex::new(r"^[A-Za-z_][\w]*$").unwrap();
+        if !package
+            .split('.')
+            .chain(class.split('$'))
+            .all(|part| re.is_match(part))
+        {
+            return None;
+        }
+
+        let value = format!("{}.{}", package, class.replace('$', "."));
+        let ident_start = value.rfind('.').unwrap() + 1;
+        Some(Fqcn {
+            package_range: 0..package.len(),
+            class_range: package.len() + 1..value.len(),
+            ident_range: ident_start..value.len(),
+            value,
+        })
+    }
+
     pub fn value(&self) -> &str {
         &self.value
     }
@@ -37,9 +65,66 @@ impl Fqcn {
         &self.value[self.package_range.start..(self.package_range.end + 1)]
     }
 
+    // the innermost class name, `Inner` in `foo.bar.Outer.Inner`
     pub fn ident(&self) -> &str {
         &self.value[self.ident_range.clone()]
     }
+
+    // the class name including any outer classes, `Outer.Inner` in
+    // `foo.bar.Outer.Inner`
+    pub fn class_name(&self) -> &str {
+        &self.value[self.class_range.clone()]
+    }
+
+    // the chain of outer classes, `Outer` in `foo.bar.Outer.Inner`
+    pub fn outer(&self) -> Option<&str> {
+        self.class_name()
+            .rsplit_once('.')
+            .map(|(outer, _ident)| outer)
+    }
+
+    pub fn is_nested(&self) -> bool {
+        self.outer().is_some()
+    }
+
+    // whether renaming this class to `other` would move it into, out of, or
+    // between outer classes, e.g. `foo.bar.Outer.Inner` to `foo.bar.Inner`
+    pub fn changes_nesting(&self, other: &Fqcn) -> bool {
+        match (self.outer(), other.outer()) {
+            (None, None) => false,
+            (Some(outer), Some(other_outer)) => {
+                outer != other_outer || self.package() != other.package()
+            }
+            _ => true,
+        }
+    }
+
+    // the outermost class, `foo.bar.Outer` for `foo.bar.Outer.Inner`
+    pub fn top_level(&self) -> Fqcn {
+        let top_level_class = self.class_name().split('.').next().unwrap();
+        let end = self.class_range.start + top_level_class.len();
+        Fqcn {
+            value: self.value[..end].to_owned(),
+            package_range: self.package_range.clone(),
+            class_range: self.class_range.start..end,
+            ident_range: self.class_range.start..end,
+        }
+    }
+
+    // the name the JVM uses for the class, as found in reflection strings and
+    // config files, e.g. `foo.bar.Outer$Inner`
+    pub fn binary_name(&self) -> String {
+        format!("{}.{}", self.package(), self.class_name().replace('.', "$"))
+    }
+
+    // path of the file declaring this class (or its outermost class),
+    // relative to the source root, e.g. `foo/bar/Baz.java`
+    pub fn source_path(&self, extension: &str) -> PathBuf {
+        let top_level = self.top_level();
+        let mut path: PathBuf = self.package().split('.').collect();
+        path.push(format!("{}.{}", top_level.ident(), extension));
+        path
+    }
 }
 
 #[cfg(test)]
@@ -55,7 +140,59 @@ mod test {
         assert_eq!("foo.bar", fqcn.package());
         assert_eq!("foo.bar.Baz", fqcn.value());
 
+        assert_eq!(
+            std::path::Path::new("foo/bar/Baz.kt"),
+            fqcn.source_path("kt")
+        );
+
         assert!(Fqcn::new("foo.bar").is_none());
-        assert_matches!(Fqcn::new("foo.bar.Baz.Smaz"), None);
+        assert!(!fqcn.is_nested());
+        assert_eq!("Baz", fqcn.class_name());
+        assert_eq!("foo.bar.Baz", fqcn.binary_name());
+        assert_matches!(Fqcn::new("foo.bar.Baz.smaz"), None);
+    }
+
+    #[test]
+    fn test_nested() {
+        let fqcn = Fqcn::new("foo.bar.Baz.Smaz.Quux").unwrap();
+        assert_eq!("foo.bar", fqcn.package());
+        assert_eq!("Quux", fqcn.ident());
+        assert_eq!("Baz.Smaz.Quux", fqcn.class_name());
+        assert_eq!(Some("Baz.Smaz"), fqcn.outer());
+        assert!(fqcn.is_nested());
+        assert_eq!("foo.bar.Baz$Smaz$Quux", fqcn.binary_name());
+        assert_eq!(Fqcn::new("foo.bar.Baz").unwrap(), fqcn.top_level());
+        assert_eq!(
+            std::path::Path::new("foo/bar/Baz.java"),
+            fqcn.source_path("java")
+        );
+    }
+
+    #[test]
+    fn test_from_binary() {
+        let fqcn = Fqcn::from_binary("foo.bar.Baz$Quux").unwrap();
+        assert_eq!(Fqcn::new("foo.bar.Baz.Quux").unwrap(), fqcn);
+
+        let obfuscated = Fqcn::from_binary("a.b$c").unwrap();
+        assert_eq!("a", obfuscated.package());
+        assert_eq!("b.c", obfuscated.class_name());
+        assert_eq!("c", obfuscated.ident());
+        assert_eq!("a.b", obfuscated.top_level().value());
+
+        assert!(Fqcn::from_binary("Baz").is_none());
+        assert!(Fqcn::from_binary("foo..Baz").is_none());
+        assert!(Fqcn::from_binary("foo.Baz$").is_none());
+    }
+
+    #[test]
+    fn test_changes_nesting() {
+        let fqcn = |value| Fqcn::new(value).unwrap();
+
+        assert!(!fqcn("foo.Bar").changes_nesting(&fqcn("baz.Quux")));
+        assert!(!fqcn("foo.Outer.Inner").changes_nesting(&fqcn("foo.Outer.Renamed")));
+        assert!(fqcn("foo.Outer.Inner").changes_nesting(&fqcn("foo.Inner")));
+        assert!(fqcn("foo.Inner").changes_nesting(&fqcn("foo.Outer.Inner")));
+        assert!(fqcn("foo.Outer.Inner").changes_nesting(&fqcn("foo.Other.Inner")));
+        assert!(fqcn("foo.Outer.Inner").changes_nesting(&fqcn("baz.Outer.Inner")));
     }
 }
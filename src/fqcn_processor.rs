@@ -1,7 +1,54 @@
-use crate::{fqcn::Fqcn, matched_file::MatchedFile};
+use regex::Regex;
 
-const PACKAGE: &str = "package ";
-const IMPORT: &str = "import ";
+use crate::{
+    fqcn::Fqcn,
+    imports::{parse_import, parse_package},
+    matched_file::MatchedFile,
+    package::Package,
+};
+
+/// How a matched file refers to the searched FQCN.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FileUsage {
+    /// the line declaring the file's package, if it is the FQCN's package (or
+    /// is in the searched package)
+    pub package_line: Option<usize>,
+    /// declares the class (with a `class`, `interface`, `enum`, etc.), or
+    /// anything in the searched package
+    pub declares: bool,
+    /// imports the FQCN, or (statically) one of its members
+    pub imports: bool,
+    /// has a plain `import` of exactly the FQCN
+    pub imports_exactly: bool,
+    /// has a wildcard import of the FQCN's package, e.g. `import foo.*`
+    pub wildcard_import: bool,
+    /// imports the top-level class that the (nested) FQCN is declared in,
+    /// e.g. `import foo.Outer` for `foo.Outer.Inner`
+    pub imports_outer: bool,
+    /// declares the top-level class that the (nested) FQCN is declared in
+    pub declares_outer: bool,
+    /// imports the FQCN under another name, e.g. `import foo.Bar as Baz`
+    pub alias: Option<String>,
+    /// refers to the FQCN by its fully qualified or binary name
+    pub qualified: bool,
+    /// refers to the bare identifier of the FQCN
+    pub ident: bool,
+}
+
+impl FileUsage {
+    pub fn same_package(&self) -> bool {
+        self.package_line.is_some()
+    }
+
+    // refers to the class without importing it explicitly, relying on being
+    // in the same package or on a wildcard import of the package
+    pub fn imports_implicitly(&self) -> bool {
+        self.ident
+            && !self.declares
+            && !self.imports_exactly
+            && (self.same_package() || self.wildcard_import)
+    }
+}
 
 pub fn process_matched_file_fqcn(
     fqcn: &Fqcn,
@@ -10,47 +57,175 @@ pub fn process_matched_file_fqcn(
     let fqcn_value = fqcn.value();
     let fqcn_packg = fqcn.package();
     let fqcn_ident = fqcn.ident();
+    let fqcn_class = fqcn.class_name();
+    let fqcn_binary = fqcn.binary_name();
+    let fqcn_outer = fqcn.top_level();
+    let is_outer_import = |line: &str| {
+        fqcn.is_nested()
+            && matches!(
+                parse_import(line),
+                Some(import) if import.path == fqcn_outer.value() && import.alias.is_none()
+            )
+    };
+
+    let declaration = declaration_regex(fqcn_ident);
+    let outer_declaration = declaration_regex(fqcn_outer.ident());
+    let is_outer_declaration = |line: &str| fqcn.is_nested() && outer_declaration.is_match(line);
 
     matched_files.retain_mut(|matched_file| {
-        let mut saw_package = false;
-        let mut saw_import = false;
-        let mut saw_ident = false;
-        let mut saw_fqcn = false;
+        let mut usage = FileUsage::default();
+        let mut saw_declaration = false;
+        let mut saw_outer_declaration = false;
 
-        matched_file.lines_mut().for_each(|line| {
+        for line in matched_file.lines() {
             let line_value = line.value();
 
-            if line_value.starts_with(IMPORT) && line_value.contains(fqcn_value) {
-                saw_import = true;
-            } else if line_value.starts_with(PACKAGE) && line_value.contains(fqcn_packg) {
-                saw_package = true;
+            if let Some(import) = parse_import(line_value) {
+                if import.imports_from(fqcn_value) {
+                    usage.imports = true;
+                    usage.imports_exactly |= import.imports_exactly(fqcn_value);
+                    if import.path == fqcn_value {
+                        if let Some(alias) = import.alias {
+                            usage.alias = Some(alias.to_owned());
+                        }
+                    }
+                } else if is_outer_import(line_value) {
+                    usage.imports_outer = true;
+                } else if !import.is_static && import.wildcard && import.path == fqcn_packg {
+                    usage.wildcard_import = true;
+                }
+            } else if parse_package(line_value) == Some(fqcn_packg) {
+                usage.package_line = Some(line.num());
+            } else {
+                saw_declaration |= declaration.is_match(line_value);
+                saw_outer_declaration |= is_outer_declaration(line_value);
             }
+        }
+        usage.declares_outer = saw_outer_declaration && usage.same_package();
 
+        matched_file.lines_mut().for_each(|line| {
             line.adjust_submatches(|submatch| {
                 // println!("adjusting: {} -> {}", submatch, &submatch[ret.clone()]);
 
-                if let Some(idx) = submatch.find(fqcn_value) {
-                    saw_fqcn = true;
+                if let Some(idx) = find_name(submatch, fqcn_value) {
+                    usage.qualified = true;
                     idx..idx + fqcn_value.len()
-                } else if let Some(idx) = submatch.find(fqcn_packg) {
+                } else if let Some(idx) = find_name(submatch, &fqcn_binary) {
+                    // `foo.Outer$Inner`, e.g. in reflection or proguard rules
+                    usage.qualified = true;
+                    idx..idx + fqcn_binary.len()
+                } else if is_outer_import(submatch) || is_outer_declaration(submatch) {
+                    // only there to make `Outer.Inner` resolve, or to tell
+                    // where a bare `Inner` does, nothing to rename
+                    0..0
+                } else if let Some(idx) = find_name(submatch, fqcn_packg) {
                     idx..idx + fqcn_packg.len()
-                } else if let Some(idx) = submatch.find(fqcn_ident) {
-                    saw_ident = true;
-                    idx..idx + fqcn_ident.len()
+                } else if let Some(range) = [fqcn_class, fqcn_ident].iter().find_map(|name| {
+                    let idx = find_name(submatch, name)?;
+                    Some(idx..idx + name.len())
+                }) {
+                    // with `import foo.Bar as Baz`, the class is referred to
+                    // as `Baz`, so a bare `Bar` must be some other class
+                    if usage.alias.is_some() {
+                        return 0..0;
+                    }
+                    // a bare nested class name only resolves to it within
+                    // its outer class, or with an import of it; anywhere
+                    // else it's some other class of the same name
+                    if fqcn.is_nested()
+                        && submatch[range.clone()] == *fqcn_ident
+                        && !usage.declares_outer
+                        && !usage.imports_exactly
+                    {
+                        return 0..0;
+                    }
+                    usage.ident = true;
+                    range
                 } else {
                     0..submatch.len()
                 }
             });
         });
 
-        // println!("saw fqcn: {}", saw_fqcn);
-        // println!("saw import: {}", saw_import);
-        saw_fqcn || saw_import || (saw_package && saw_ident)
+        usage.declares = match fqcn.is_nested() {
+            true => saw_declaration && usage.declares_outer,
+            false => saw_declaration && usage.same_package(),
+        };
+
+        let keep = usage.qualified
+            || usage.imports
+            || (usage.ident
+                && (usage.same_package() || usage.wildcard_import || usage.imports_outer));
+        matched_file.set_usage(usage);
+        keep
     });
 
     matched_files
 }
 
+pub fn process_matched_file_package(
+    package: &Package,
+    mut matched_files: Vec<MatchedFile>,
+) -> Vec<MatchedFile> {
+    let package_value = package.value();
+
+    matched_files.retain_mut(|matched_file| {
+        let mut usage = FileUsage::default();
+
+        for line in matched_file.lines() {
+            if let Some(file_package) = parse_package(line.value()) {
+                if package.contains(file_package) {
+                    usage.package_line = Some(line.num());
+                    usage.declares = true;
+                }
+            }
+        }
+
+        // everything under the package is referred to by its fully qualified
+        // name, so just trim off the character in front of the match
+        matched_file.lines_mut().for_each(|line| {
+            line.adjust_submatches(|submatch| match find_name(submatch, package_value) {
+                Some(idx) => {
+                    usage.qualified = true;
+                    idx..idx + package_value.len()
+                }
+                None => 0..0,
+            });
+        });
+
+        let keep = usage.qualified;
+        matched_file.set_usage(usage);
+        keep
+    });
+
+    matched_files
+}
+
+// `class Bar`, `object Bar` and the like
+fn declaration_regex(ident: &str) -> Regex {
+    Regex::new(&format!(
+        r"\b(class|interface|enum|object|record|typealias)\s+{}\b",
+        regex::escape(ident)
+    ))
+    .unwrap()
+}
+
+// the first occurrence of `name` in `haystack` that isn't part of a longer
+// identifier, e.g. the package `a` in `package a;`
+fn find_name(haystack: &str, name: &str) -> Option<usize> {
+    let is_ident_char = |c: char| c.is_alphanumeric() || c == '_' || c == '$';
+
+    haystack
+        .match_indices(name)
+        .map(|(idx, _)| idx)
+        .find(|&idx| {
+            let before = haystack[..idx].chars().next_back();
+            let after = haystack[idx + name.len()..].chars().next();
+            !matches!(before, Some(c) if is_ident_char(c))
+                && !matches!(after, Some(c) if is_ident_char(c))
+        })
+}
+
 #[cfg(test)]
 mod test {
     use itertools::assert_equal;
@@ -58,9 +233,10 @@ mod test {
     use crate::{
         fqcn::Fqcn,
         matched_file::{Line, MatchedFile},
+        package::Package,
     };
 
-    use super::process_matched_file_fqcn;
+    use super::{process_matched_file_fqcn, process_matched_file_package, FileUsage};
 
     #[test]
     fn test_works() {
@@ -166,4 +342,354 @@ mod test {
 
         assert_eq!(vec![] as Vec<MatchedFile>, matches);
     }
+
+    #[test]
+    fn test_classifies_declaring_and_sibling() {
+        let fqcn = Fqcn::new("foo.bar.Baz").unwrap();
+        let matches = process_matched_file_fqcn(
+            &fqcn,
+            vec![
+                MatchedFile::new(
+                    "foo/bar/Baz.java",
+                    vec![
+                        Line::new(0, "package foo.bar;", vec![0..16]),
+                        Line::new(2, "public class Baz {", vec![13..16]),
+                    ],
+                ),
+                MatchedFile::new(
+                    "foo/bar/Sibling.java",
+                    vec![
+                        Line::new(0, "package foo.bar;", vec![0..16]),
+                        Line::new(4, "  Baz baz;", vec![2..5]),
+                    ],
+                ),
+                MatchedFile::new(
+                    "foo/bar/sub/Other.java",
+                    vec![
+                        Line::new(0, "package foo.bar.sub;", vec![]),
+                        Line::new(4, "  Baz baz;", vec![2..5]),
+                    ],
+                ),
+            ],
+        );
+
+        assert_eq!(2, matches.len());
+        assert_eq!(
+            &FileUsage {
+                package_line: Some(0),
+                declares: true,
+                ident: true,
+                ..Default::default()
+            },
+            matches[0].usage()
+        );
+        assert_eq!(
+            &FileUsage {
+                package_line: Some(0),
+                ident: true,
+                ..Default::default()
+            },
+            matches[1].usage()
+        );
+        assert!(matches[1].usage().imports_implicitly());
+    }
+
+    #[test]
+    fn test_static_and_nested_imports() {
+        let fqcn = Fqcn::new("foo.bar.Baz").unwrap();
+        let matches = process_matched_file_fqcn(
+            &fqcn,
+            vec![
+                MatchedFile::new(
+                    "Static.java",
+                    vec![Line::new(
+                        2,
+                        "import static foo.bar.Baz.CONSTANT;",
+                        vec![0..35],
+                    )],
+                ),
+                MatchedFile::new(
+                    "Nested.java",
+                    vec![Line::new(2, "import foo.bar.Baz.Inner;", vec![0..25])],
+                ),
+                MatchedFile::new(
+                    "Prefix.java",
+                    vec![
+                        Line::new(2, "import foo.bar.BazQuux;", vec![]),
+                        Line::new(4, "  Baz baz;", vec![2..5]),
+                    ],
+                ),
+            ],
+        );
+
+        assert_eq!(2, matches.len());
+        assert!(matches[0].usage().imports);
+        assert_equal(
+            [
+                (false, "import static "),
+                (true, "foo.bar.Baz"),
+                (false, ".CONSTANT;"),
+            ],
+            matches[0].lines().next().unwrap().iter().take(100),
+        );
+        assert!(matches[1].usage().imports);
+        assert_equal(
+            [
+                (false, "import "),
+                (true, "foo.bar.Baz"),
+                (false, ".Inner;"),
+            ],
+            matches[1].lines().next().unwrap().iter().take(100),
+        );
+    }
+
+    #[test]
+    fn test_wildcard_import() {
+        let fqcn = Fqcn::new("foo.bar.Baz").unwrap();
+        let matches = process_matched_file_fqcn(
+            &fqcn,
+            vec![
+                MatchedFile::new(
+                    "Wildcard.java",
+                    vec![
+                        Line::new(2, "import foo.bar.*;", vec![0..17]),
+                        Line::new(4, "  Baz baz;", vec![2..5]),
+                    ],
+                ),
+                MatchedFile::new(
+                    "Unused.java",
+                    vec![Line::new(2, "import foo.bar.*;", vec![0..17])],
+                ),
+            ],
+        );
+
+        assert_eq!(1, matches.len());
+        assert!(matches[0].usage().wildcard_import);
+        assert!(matches[0].usage().imports_implicitly());
+        assert_equal(
+            [(false, "import "), (true, "foo.bar"), (false, ".*;")],
+            matches[0].lines().next().unwrap().iter().take(100),
+        );
+    }
+
+    #[test]
+    fn test_alias_import() {
+        let fqcn = Fqcn::new("foo.bar.Baz").unwrap();
+        let matches = process_matched_file_fqcn(
+            &fqcn,
+            vec![MatchedFile::new(
+                "Alias.kt",
+                vec![
+                    Line::new(2, "import foo.bar.Baz as Quux", vec![0..26]),
+                    Line::new(4, "val baz: Baz = Quux()", vec![9..12]),
+                ],
+            )],
+        );
+
+        assert_eq!(1, matches.len());
+        assert_eq!(Some("Quux"), matches[0].usage().alias.as_deref());
+        assert!(!matches[0].usage().ident);
+
+        let mut lines = matches[0].lines();
+        assert_equal(
+            [
+                (false, "import "),
+                (true, "foo.bar.Baz"),
+                (false, " as Quux"),
+            ],
+            lines.next().unwrap().iter().take(100),
+        );
+        assert_equal(
+            [(false, "val baz: Baz = Quux()")],
+            lines.next().unwrap().iter().take(100),
+        );
+    }
+
+    #[test]
+    fn test_nested() {
+        let fqcn = Fqcn::new("foo.bar.Outer.Inner").unwrap();
+        let matches = process_matched_file_fqcn(
+            &fqcn,
+            vec![
+                MatchedFile::new(
+                    "OuterImport.java",
+                    vec![
+                        Line::new(2, "import foo.bar.Outer;", vec![0..21]),
+                        Line::new(4, "  Outer.Inner inner;", vec![2..13]),
+                    ],
+                ),
+                MatchedFile::new(
+                    "InnerImport.kt",
+                    vec![
+                        Line::new(2, "import foo.bar.Outer.Inner", vec![0..26]),
+                        Line::new(4, "val inner: Inner", vec![11..16]),
+                    ],
+                ),
+                MatchedFile::new(
+                    "Reflection.java",
+                    vec![Line::new(
+                        4,
+                        "Class.forName(\"foo.bar.Outer$Inner\");",
+                        vec![15..34],
+                    )],
+                ),
+                MatchedFile::new(
+                    "Unrelated.java",
+                    vec![Line::new(4, "  Inner inner;", vec![2..7])],
+                ),
+            ],
+        );
+
+        assert_eq!(3, matches.len());
+        assert!(!matches[0].usage().declares_outer);
+
+        assert!(matches[0].usage().imports_outer);
+        let mut lines = matches[0].lines();
+        assert_equal(
+            [(false, "import foo.bar.Outer;")],
+            lines.next().unwrap().iter().take(100),
+        );
+        assert_equal(
+            [(false, "  "), (true, "Outer.Inner"), (false, " inner;")],
+            lines.next().unwrap().iter().take(100),
+        );
+
+        assert!(matches[1].usage().imports);
+        assert_equal(
+            [(false, "import "), (true, "foo.bar.Outer.Inner")],
+            matches[1].lines().next().unwrap().iter().take(100),
+        );
+
+        assert!(matches[2].usage().qualified);
+        assert_equal(
+            [
+                (false, "Class.forName(\""),
+                (true, "foo.bar.Outer$Inner"),
+                (false, "\");"),
+            ],
+            matches[2].lines().next().unwrap().iter().take(100),
+        );
+    }
+
+    #[test]
+    fn test_nested_bare_name() {
+        let fqcn = Fqcn::new("foo.bar.Outer.Inner").unwrap();
+        let matches = process_matched_file_fqcn(
+            &fqcn,
+            vec![
+                MatchedFile::new(
+                    "foo/bar/Outer.java",
+                    vec![
+                        Line::new(0, "package foo.bar;", vec![0..16]),
+                        Line::new(2, "public class Outer {", vec![7..18]),
+                        Line::new(3, "  static class Inner {}", vec![15..20]),
+                        Line::new(4, "  Inner inner;", vec![2..7]),
+                    ],
+                ),
+                // the same package, with its own `Inner`
+                MatchedFile::new(
+                    "foo/bar/Other.java",
+                    vec![
+                        Line::new(0, "package foo.bar;", vec![0..16]),
+                        Line::new(3, "  static class Inner {}", vec![15..20]),
+                        Line::new(4, "  Inner b = new Inner();", vec![2..7, 16..21]),
+                    ],
+                ),
+                // importing the outer class only makes `Outer.Inner` resolve
+                MatchedFile::new(
+                    "Wildcard.java",
+                    vec![
+                        Line::new(0, "package net.baz;", vec![]),
+                        Line::new(2, "import foo.bar.*;", vec![0..17]),
+                        Line::new(3, "import foo.bar.Outer;", vec![0..21]),
+                        Line::new(4, "  Inner inner;", vec![2..7]),
+                    ],
+                ),
+                MatchedFile::new(
+                    "Imported.java",
+                    vec![
+                        Line::new(2, "import foo.bar.Outer.Inner;", vec![0..27]),
+                        Line::new(4, "  Inner inner;", vec![2..7]),
+                    ],
+                ),
+            ],
+        );
+
+        assert_equal(
+            ["foo/bar/Outer.java", "Imported.java"],
+            matches.iter().map(|mf| mf.file_path()),
+        );
+
+        assert!(matches[0].usage().declares_outer);
+        assert!(matches[0].usage().declares);
+        assert_equal(
+            [
+                vec![(false, "package "), (true, "foo.bar"), (false, ";")],
+                vec![(false, "public class Outer {")],
+                vec![(false, "  static class "), (true, "Inner"), (false, " {}")],
+                vec![(false, "  "), (true, "Inner"), (false, " inner;")],
+            ],
+            matches[0]
+                .lines()
+                .map(|line| line.iter().take(100).collect::<Vec<_>>()),
+        );
+
+        assert!(matches[1].usage().imports_exactly);
+        assert_equal(
+            [(false, "  "), (true, "Inner"), (false, " inner;")],
+            matches[1].lines().nth(1).unwrap().iter().take(100),
+        );
+    }
+
+    #[test]
+    fn test_package() {
+        let package = Package::new("javax.servlet").unwrap();
+        let matches = process_matched_file_package(
+            &package,
+            vec![
+                MatchedFile::new(
+                    "javax/servlet/http/HttpServlet.java",
+                    vec![
+                        Line::new(0, "package javax.servlet.http;", vec![7..21]),
+                        Line::new(2, "import javax.servlet.Filter;", vec![6..20]),
+                    ],
+                ),
+                MatchedFile::new(
+                    "Usage.java",
+                    vec![Line::new(4, "  javax.servlet.Filter filter;", vec![1..15])],
+                ),
+            ],
+        );
+
+        assert_eq!(2, matches.len());
+        assert!(matches[0].usage().declares);
+        assert_eq!(Some(0), matches[0].usage().package_line);
+        let mut lines = matches[0].lines();
+        assert_equal(
+            [
+                (false, "package "),
+                (true, "javax.servlet"),
+                (false, ".http;"),
+            ],
+            lines.next().unwrap().iter().take(100),
+        );
+        assert_equal(
+            [
+                (false, "import "),
+                (true, "javax.servlet"),
+                (false, ".Filter;"),
+            ],
+            lines.next().unwrap().iter().take(100),
+        );
+
+        assert!(!matches[1].usage().declares);
+        assert_equal(
+            [
+                (false, "  "),
+                (true, "javax.servlet"),
+                (false, ".Filter filter;"),
+            ],
+            matches[1].lines().next().unwrap().iter().take(100),
+        );
+    }
 }
@@ -0,0 +1,341 @@
+use std::{
+    error::Error,
+    io, mem,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Instant,
+};
+
+use grep_matcher::{LineTerminator, Matcher};
+use grep_regex::{RegexMatcher, RegexMatcherBuilder};
+use grep_searcher::{
+    BinaryDetection, Searcher, SearcherBuilder, Sink, SinkContext, SinkError, SinkFinish, SinkMatch,
+};
+use ignore::{WalkBuilder, WalkState};
+use parking_lot::{Mutex, MutexGuard};
+
+use crate::{
+    event_log::EventLog,
+    matched_file::{hash, Line, MatchedFile},
+    search_backend::SearchStats,
+    text::Encoding,
+};
+
+/// A search run in-process with ripgrep's own crates, walking the tree on a
+/// thread pool. Finds the same lines, with the same context, that `rg` does
+/// with the arguments of [`Search::rg_args`](crate::search::Search::rg_args).
+pub struct GrepWorker {
+    name: String,
+    quit: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    results: Arc<Mutex<Vec<MatchedFile>>>,
+    stats: Arc<Mutex<SearchStats>>,
+    errors: Arc<Mutex<Vec<String>>>,
+}
+
+impl GrepWorker {
+    /// Starts searching `base_dir` for `pattern`, with `context` lines around
+    /// each match, calling `notify` each time a matched file has been added
+    /// to [`GrepWorker::results`].
+    pub fn new<N, S>(
+        notify: N,
+        name: S,
+        events: EventLog,
+        pattern: &str,
+        context: usize,
+        base_dir: &str,
+    ) -> Result<GrepWorker, Box<dyn Error>>
+    where
+        N: Fn() + Send + Sync + 'static,
+        S: Into<String>,
+    {
+        // as `rg --crlf` builds it
+        let matcher = RegexMatcherBuilder::new()
+            .multi_line(true)
+            .crlf(true)
+            .build(pattern)?;
+
+        let name = name.into();
+        let quit: Arc<AtomicBool> = Default::default();
+        let results: Arc<Mutex<Vec<MatchedFile>>> = Default::default();
+        let stats: Arc<Mutex<SearchStats>> = Default::default();
+        let errors: Arc<Mutex<Vec<String>>> = Default::default();
+        let walker = WalkBuilder::new(base_dir)
+            .add_custom_ignore_filename(".rgignore")
+            .build_parallel();
+
+        let thread = {
+            let (name, quit, results) = (name.clone(), quit.clone(), results.clone());
+            let (stats, errors) = (stats.clone(), errors.clone());
+            let notify: Arc<dyn Fn() + Send + Sync> = Arc::new(notify);
+            thread::spawn(move || {
+                let start = Instant::now();
+                walker.run(|| {
+                    let mut searcher = SearcherBuilder::new()
+                        .line_number(true)
+                        .line_terminator(LineTerminator::crlf())
+                        .before_context(context)
+                        .after_context(context)
+                        .binary_detection(BinaryDetection::quit(b'\x00'))
+                        .build();
+                    let (matcher, notify) = (matcher.clone(), notify.clone());
+                    let (name, quit, results) = (name.clone(), quit.clone(), results.clone());
+                    let (stats, errors) = (stats.clone(), errors.clone());
+                    let mut events = events.clone();
+
+                    Box::new(move |entry| {
+                        if quit.load(Ordering::Relaxed) {
+                            return WalkState::Quit;
+                        }
+                        let entry = match entry {
+                            Ok(entry) => entry,
+                            Err(err) => {
+                                events.error(format!("grep {}: {}", name, err));
+                                errors.lock().push(err.to_string());
+                                return WalkState::Continue;
+                            }
+                        };
+                        if !entry.file_type().is_some_and(|ft| ft.is_file()) {
+                            return WalkState::Continue;
+                        }
+
+                        let file_path = entry.path().to_string_lossy().into_owned();
+                        // read once, both to search and to hash
+                        let contents = match std::fs::read(entry.path()) {
+                            Ok(contents) => contents,
+                            Err(err) => {
+                                events.error(format!("grep {}: {}: {}", name, file_path, err));
+                                errors.lock().push(format!("{}: {}", file_path, err));
+                                return WalkState::Continue;
+                            }
+                        };
+                        let mut sink = LineSink {
+                            matcher: &matcher,
+                            encoding: Encoding::of(&contents),
+                            quit: &quit,
+                            lines: vec![],
+                            matched: false,
+                            stats: Default::default(),
+                        };
+                        if let Err(err) = searcher.search_slice(&matcher, &contents, &mut sink) {
+                            events.error(format!("grep {}: {}: {}", name, file_path, err));
+                            errors.lock().push(format!("{}: {}", file_path, err));
+                            return WalkState::Continue;
+                        }
+                        *stats.lock() += sink.stats;
+
+                        if sink.matched {
+                            events.info(format!("grep {}: match in `{:?}`", name, file_path));
+                            let mut found = MatchedFile::new(file_path, sink.lines);
+                            // to tell if the file changes before it's replaced in
+                            found.set_content_hash(Some(hash(&contents)));
+                            results.lock().push(found);
+                            notify();
+                        }
+                        WalkState::Continue
+                    })
+                });
+                stats.lock().elapsed = start.elapsed();
+            })
+        };
+
+        Ok(GrepWorker {
+            name,
+            quit,
+            thread: Some(thread),
+            results,
+            stats,
+            errors,
+        })
+    }
+
+    pub fn kill_and_wait(&mut self) -> Result<(), Box<dyn Error>> {
+        // a finished search keeps its stats
+        if !self.finished() {
+            self.quit.store(true, Ordering::Relaxed);
+        }
+        self.join()
+    }
+
+    pub fn finished(&mut self) -> bool {
+        self.thread
+            .as_ref()
+            .map(|thread| thread.is_finished())
+            .unwrap_or(true)
+    }
+
+    /// How much was searched, once the whole tree has been.
+    pub fn stats(&self) -> Option<SearchStats> {
+        let finished = self
+            .thread
+            .as_ref()
+            .is_none_or(|thread| thread.is_finished());
+        match finished && !self.quit.load(Ordering::Relaxed) {
+            true => Some(*self.stats.lock()),
+            false => None,
+        }
+    }
+
+    /// The files and directories that couldn't be read so far.
+    pub fn errors(&self) -> Vec<String> {
+        self.errors.lock().clone()
+    }
+
+    pub fn results(&self) -> MutexGuard<'_, Vec<MatchedFile>> {
+        self.results.lock()
+    }
+
+    /// Blocks until the whole tree has been searched, returning every
+    /// matched file.
+    pub fn wait(&mut self) -> Result<Vec<MatchedFile>, Box<dyn Error>> {
+        self.join()?;
+        Ok(mem::take(&mut *self.results.lock()))
+    }
+
+    fn join(&mut self) -> Result<(), Box<dyn Error>> {
+        match self.thread.take() {
+            Some(thread) => thread
+                .join()
+                .map_err(|err| format!("{} error: {:?}", self.name, err).into()),
+            None => Ok(()),
+        }
+    }
+}
+
+// collects the matched and context lines of one file, as `rg --json` reports
+// them
+struct LineSink<'a> {
+    matcher: &'a RegexMatcher,
+    // of the whole file, which each line is decoded as
+    encoding: Encoding,
+    quit: &'a AtomicBool,
+    lines: Vec<Line>,
+    matched: bool,
+    stats: SearchStats,
+}
+
+impl<'a> LineSink<'a> {
+    fn push_line(&mut self, line_num: u64, line: &[u8], matched: bool) -> Result<(), io::Error> {
+        let mut submatches = vec![];
+        if matched {
+            self.matcher
+                .find_iter(line, |submatch| {
+                    self.stats.matches += 1;
+                    // empty matches, e.g. of `^`, have nothing to replace
+                    if !submatch.is_empty() {
+                        submatches.push(
+                            self.encoding.decoded_offset(line, submatch.start())
+                                ..self.encoding.decoded_offset(line, submatch.end()),
+                        );
+                    }
+                    true
+                })
+                .map_err(io::Error::error_message)?;
+        }
+
+        // lines are 1-indexed, sub 1 to make it zero indexed
+        self.lines.push(Line::new(
+            line_num as usize - 1,
+            self.encoding.decode(line),
+            submatches,
+        ));
+        Ok(())
+    }
+}
+
+impl<'a> Sink for LineSink<'a> {
+    type Error = io::Error;
+
+    fn matched(&mut self, _: &Searcher, mat: &SinkMatch<'_>) -> Result<bool, io::Error> {
+        let first = mat.line_number().unwrap_or(1);
+        for (idx, line) in mat.lines().enumerate() {
+            self.push_line(first + idx as u64, line, true)?;
+        }
+        self.matched = true;
+        Ok(!self.quit.load(Ordering::Relaxed))
+    }
+
+    fn context(&mut self, _: &Searcher, context: &SinkContext<'_>) -> Result<bool, io::Error> {
+        self.push_line(context.line_number().unwrap_or(1), context.bytes(), false)?;
+        Ok(!self.quit.load(Ordering::Relaxed))
+    }
+
+    fn finish(&mut self, _: &Searcher, finish: &SinkFinish) -> Result<(), io::Error> {
+        self.stats.files_searched += 1;
+        self.stats.bytes_searched += finish.byte_count();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use itertools::assert_equal;
+
+    use crate::{matched_file::file_hash, search::Search, search_backend::GrepBackend};
+
+    #[test]
+    fn test_search() {
+        let dir = std::env::temp_dir().join(format!("fqn-renamer-grep-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("com/foo")).unwrap();
+        std::fs::write(
+            dir.join("com/foo/Bar.java"),
+            "\u{feff}package com.foo;\r\n\r\nclass Bar {}\r\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("User.java"),
+            "package net.baz;\n\nimport com.foo.Bar;\n\nclass User {\n  Bar bar;\n}\n",
+        )
+        .unwrap();
+        // not a match: another class of the same name
+        std::fs::write(dir.join("Other.java"), "package x;\n\nclass Bar {}\n").unwrap();
+
+        let output = Search::new("com.foo.Bar")
+            .run(&GrepBackend, dir.to_str().unwrap(), &mut Default::default())
+            .unwrap();
+        let stats = output.stats.unwrap();
+        assert_eq!((3, 5), (stats.files_searched, stats.matches));
+        assert!(output.errors.is_empty());
+
+        let mut found = output.matches;
+        found.sort_by(|a, b| a.file_path().cmp(b.file_path()));
+
+        assert_eq!(2, found.len());
+        assert!(found[0].file_path().ends_with("User.java"));
+        assert_equal(
+            [
+                (0, "package net.baz;\n", 0),
+                (1, "\n", 0),
+                (2, "import com.foo.Bar;\n", 1),
+                (3, "\n", 0),
+                (4, "class User {\n", 0),
+                (5, "  Bar bar;\n", 1),
+                (6, "}\n", 0),
+            ],
+            found[0]
+                .lines()
+                .map(|line| (line.num(), line.value(), line.num_submatches())),
+        );
+        // without the byte order mark, with CRLF line endings
+        assert_equal(
+            [
+                (0, "package com.foo;\r\n", 1),
+                (1, "\r\n", 0),
+                (2, "class Bar {}\r\n", 1),
+            ],
+            found[1]
+                .lines()
+                .map(|line| (line.num(), line.value(), line.num_submatches())),
+        );
+        // of the whole file, byte order mark and all
+        assert_eq!(
+            file_hash(found[1].file_path()).ok().as_deref(),
+            found[1].content_hash()
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
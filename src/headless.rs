@@ -0,0 +1,465 @@
+use std::{
+    error::Error,
+    io::{self, Write},
+    path::Path,
+};
+
+use clap::{Args, Subcommand};
+use fqn_renamer::{
+    backup::{BackupSet, BACKUPS_DIR},
+    diff,
+    event_log::EventLog,
+    mapping::{self, Mapping},
+    matched_file::{LineKind, MatchedFile},
+    plan_file::PlanFile,
+    rename::RenamePlan,
+    search::Search,
+    search_backend::{SearchBackend, SearchStats},
+};
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Search for usages of an identifier or FQCN and print the matches
+    Search {
+        /// Identifier, FQCN or package to search for, e.g. `com.foo.Bar`
+        find: String,
+        /// Directory to search
+        #[clap(default_value = ".")]
+        dir: String,
+    },
+
+    /// Rename an identifier or FQCN and print a summary of the changes
+    Rename {
+        /// Identifier, FQCN or package to rename, e.g. `com.foo.Bar` or
+        /// `javax.servlet`
+        find: String,
+        /// New identifier, FQCN or package, e.g. `net.baz.Quux` or
+        /// `jakarta.servlet`
+        replace: String,
+        /// Directory to search
+        #[clap(default_value = ".")]
+        dir: String,
+        #[clap(flatten)]
+        output: Output,
+    },
+
+    /// Rename every pair in a mapping file at once
+    Batch {
+        /// File of `<find> <replace>` pairs, as plain text (one pair per
+        /// line), `.csv` (e.g. `androidx-class-mapping.csv`), `.json`, or a
+        /// ProGuard `mapping.txt`
+        mappings: String,
+        /// Directory to search
+        #[clap(default_value = ".")]
+        dir: String,
+        /// Rename each pair from right to left, e.g. to de-obfuscate with a
+        /// ProGuard mapping
+        #[clap(long)]
+        reverse: bool,
+        #[clap(flatten)]
+        output: Output,
+    },
+
+    /// Apply a plan saved with `--save-plan`, unless any of its files changed
+    /// since
+    Apply {
+        /// Plan file to apply
+        plan: String,
+        #[clap(flatten)]
+        output: Output,
+    },
+
+    /// Undo the last rename, unless any of its files changed since
+    Undo {
+        /// Directory the rename searched
+        #[clap(default_value = ".")]
+        dir: String,
+    },
+
+    /// Manage the backups kept of each rename, for undo
+    #[clap(subcommand)]
+    Backups(BackupsCommand),
+
+    /// Report which classes and packages in a mapping file are referenced,
+    /// without renaming anything
+    References {
+        /// File of `<find> <replace>` pairs, as for `batch`
+        mappings: String,
+        /// Directory to search
+        #[clap(default_value = ".")]
+        dir: String,
+        /// Look for the right hand side of each pair instead
+        #[clap(long)]
+        reverse: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum BackupsCommand {
+    /// List the backup sets, oldest first
+    List {
+        /// Directory the renames searched
+        #[clap(default_value = ".")]
+        dir: String,
+    },
+
+    /// Remove old backup sets; the renames they were taken for can no
+    /// longer be undone
+    Prune {
+        /// Directory the renames searched
+        #[clap(default_value = ".")]
+        dir: String,
+        /// Number of the newest backup sets to keep
+        #[clap(long, default_value = "0")]
+        keep: usize,
+    },
+}
+
+/// What to do with a planned rename instead of, or before, applying it.
+#[derive(Args)]
+pub struct Output {
+    /// Print the planned replacements without writing any files
+    #[clap(long, conflicts_with = "save-plan")]
+    dry_run: bool,
+    /// Save the plan to a JSON file for review instead of renaming anything,
+    /// see `apply`
+    #[clap(long, value_name = "FILE")]
+    save_plan: Option<String>,
+    /// Print the changes as a unified diff instead of writing them, e.g. for
+    /// `git apply`
+    #[clap(long)]
+    diff: bool,
+    /// Lines of context around each change in the diff
+    #[clap(short = 'U', long, value_name = "LINES", default_value = "3")]
+    unified: usize,
+    /// Write the diff to a file instead of stdout
+    #[clap(short, long, value_name = "FILE", requires = "diff")]
+    output: Option<String>,
+}
+
+pub fn run(command: Command, backend: &dyn SearchBackend) -> Result<(), Box<dyn Error>> {
+    let mut events = EventLog::default();
+
+    match command {
+        Command::Search { find, dir } => {
+            let found_matches = search(backend, &dir, &find, &mut events)?;
+            print_matches(&found_matches);
+            println!(
+                "found {} matches in {} files",
+                num_lines(&found_matches),
+                found_matches.len()
+            );
+            Ok(())
+        }
+
+        Command::Rename {
+            find,
+            replace,
+            dir,
+            output,
+        } => {
+            let found_matches = search(backend, &dir, &find, &mut events)?;
+            let plan = RenamePlan::new(&find, &replace, &found_matches);
+            finish(&plan, &dir, &output, &mut events)
+        }
+
+        Command::Batch {
+            mappings,
+            dir,
+            reverse,
+            output,
+        } => {
+            let (skipped, mappings): (Vec<_>, Vec<_>) = read_mappings(&mappings, reverse)?
+                .into_iter()
+                .partition(|mapping| mapping.unsupported().is_some());
+            for mapping in skipped.iter() {
+                eprintln!(
+                    "skipping {} -> {}: {}",
+                    mapping.find,
+                    mapping.replace,
+                    mapping.unsupported().unwrap_or_default()
+                );
+            }
+            if mappings.is_empty() {
+                Err(format!("all {} mappings were skipped", skipped.len()))?;
+            }
+            let batch = search_batch(backend, &dir, &mappings, &mut events)?;
+            print_references(&batch);
+
+            let plan = RenamePlan::new_batch(
+                batch
+                    .iter()
+                    .map(|(mapping, found_matches)| (*mapping, found_matches.as_slice())),
+            );
+            if plan.is_empty() && plan.conflicts.is_empty() {
+                Err(format!(
+                    "no matches found for any of {} mappings",
+                    mappings.len()
+                ))?;
+            }
+            finish(&plan, &dir, &output, &mut events)
+        }
+
+        Command::Apply { plan, output } => {
+            let plan_file = PlanFile::read(&plan)?;
+            for path in plan_file.changed_files() {
+                eprintln!("changed since the plan was made: {}", path);
+            }
+            // its paths are relative to where it was made, and applied from,
+            // which keeps its backups
+            finish(&plan_file.to_plan()?, ".", &output, &mut events)
+        }
+
+        Command::Undo { dir } => {
+            let backups = BackupSet::latest(&dir)?.ok_or("no rename to undo")?;
+            let moves = match backups.journal() {
+                Some(journal) => {
+                    for path in journal.changed_files() {
+                        eprintln!("changed since the rename: {}", path);
+                    }
+                    journal.moves.clone()
+                }
+                None => vec![],
+            };
+            let num_files = backups.undo(&mut events)?;
+            for file_move in moves.iter() {
+                println!("moved {} back to {}", file_move.to, file_move.from);
+            }
+            println!("restored {} files", num_files);
+            Ok(())
+        }
+
+        Command::Backups(BackupsCommand::List { dir }) => {
+            let backups = BackupSet::list(&dir)?;
+            for backup in backups.iter() {
+                match backup.journal() {
+                    Some(journal) => println!(
+                        "{}: {} files, {} moves",
+                        backup.name(),
+                        journal.files.len(),
+                        journal.moves.len()
+                    ),
+                    None => println!("{}: no manifest", backup.name()),
+                }
+            }
+            let backups_dir = Path::new(&dir).join(BACKUPS_DIR);
+            println!(
+                "{} backup sets in {}",
+                backups.len(),
+                backups_dir.display()
+            );
+            Ok(())
+        }
+
+        Command::Backups(BackupsCommand::Prune { dir, keep }) => {
+            let pruned = BackupSet::prune(&dir, keep)?;
+            for backup in pruned.iter() {
+                println!("removed {}", backup.name());
+            }
+            println!("removed {} backup sets", pruned.len());
+            Ok(())
+        }
+
+        Command::References {
+            mappings,
+            dir,
+            reverse,
+        } => {
+            let mappings = read_mappings(&mappings, reverse)?;
+            let batch = search_batch(backend, &dir, &mappings, &mut events)?;
+            print_references(&batch);
+            Ok(())
+        }
+    }
+}
+
+fn read_mappings(path: &str, reverse: bool) -> Result<Vec<Mapping>, Box<dyn Error>> {
+    let mappings = mapping::read_mappings(path)?;
+    if reverse {
+        return Ok(mappings.iter().map(Mapping::reversed).collect());
+    }
+    Ok(mappings)
+}
+
+// each mapping of a batch, with the matches of its own search
+type Batch<'a> = Vec<(&'a Mapping, Vec<MatchedFile>)>;
+
+// searches for every mapping in a single pass over the tree, then splits
+// what was found between them
+fn search_batch<'a>(
+    backend: &dyn SearchBackend,
+    base_dir: &str,
+    mappings: &'a [Mapping],
+    events: &mut EventLog,
+) -> Result<Batch<'a>, Box<dyn Error>> {
+    let search = Search::for_batch(mappings);
+    let output = search.run(backend, base_dir, events)?;
+    print_errors(&output.errors);
+    print_stats(backend, output.stats);
+
+    Ok(mappings.iter().zip(search.split(output.matches)).collect())
+}
+
+// the mappings that are used anywhere, leaving out the (usually many) that
+// aren't
+fn print_references(batch: &[(&Mapping, Vec<MatchedFile>)]) {
+    let mut num_referenced = 0;
+    for (mapping, found_matches) in batch {
+        if found_matches.is_empty() {
+            continue;
+        }
+        num_referenced += 1;
+        println!(
+            "{} -> {}: {} files",
+            mapping.find,
+            mapping.replace,
+            found_matches.len()
+        );
+    }
+    println!(
+        "{} of {} mappings are referenced",
+        num_referenced,
+        batch.len()
+    );
+}
+
+// saves the plan or prints its diff if asked to, or else applies it
+fn finish(
+    plan: &RenamePlan,
+    base_dir: &str,
+    output: &Output,
+    events: &mut EventLog,
+) -> Result<(), Box<dyn Error>> {
+    if !plan.conflicts.is_empty() {
+        for conflict in plan.conflicts.iter() {
+            eprintln!("conflict: {}", conflict);
+        }
+        Err(format!(
+            "{} conflicts, not renaming anything",
+            plan.conflicts.len()
+        ))?;
+    }
+
+    if let Some(path) = &output.save_plan {
+        PlanFile::new(plan)?.write(path)?;
+        println!(
+            "saved the plan to replace {} lines in {} files to {}",
+            num_lines(&plan.replacements),
+            plan.replacements.len(),
+            path
+        );
+        return Ok(());
+    }
+
+    if output.diff {
+        let diff = diff::unified_diff(plan, base_dir, output.unified)?;
+        match &output.output {
+            Some(path) => {
+                std::fs::write(path, diff)?;
+                println!(
+                    "wrote the diff of {} files to {}",
+                    plan.replacements.len(),
+                    path
+                );
+            }
+            None => io::stdout().write_all(&diff)?,
+        }
+        return Ok(());
+    }
+
+    apply(plan, base_dir, output.dry_run, events)
+}
+
+fn apply(
+    plan: &RenamePlan,
+    base_dir: &str,
+    dry_run: bool,
+    events: &mut EventLog,
+) -> Result<(), Box<dyn Error>> {
+    if dry_run {
+        print_matches(&plan.replacements);
+        for file_move in plan.moves.iter() {
+            println!("would move {} to {}", file_move.from, file_move.to);
+        }
+        println!(
+            "would replace {} lines in {} files",
+            num_lines(&plan.replacements),
+            plan.replacements.len()
+        );
+        return Ok(());
+    }
+
+    let num_replacements = plan.apply(base_dir, events)?;
+    for replacement in plan.replacements.iter() {
+        println!(
+            "{}: {} lines",
+            replacement.file_path(),
+            replacement.lines().len()
+        );
+    }
+    for file_move in plan.moves.iter() {
+        println!("moved {} to {}", file_move.from, file_move.to);
+    }
+    println!(
+        "replaced {} matches in {} files",
+        num_replacements,
+        plan.replacements.len()
+    );
+    Ok(())
+}
+
+fn search(
+    backend: &dyn SearchBackend,
+    base_dir: &str,
+    find: &str,
+    events: &mut EventLog,
+) -> Result<Vec<MatchedFile>, Box<dyn Error>> {
+    let output = Search::new(find).run(backend, base_dir, events)?;
+    print_errors(&output.errors);
+    print_stats(backend, output.stats);
+    if output.matches.is_empty() {
+        Err(format!("no matches found for `{}`", find))?;
+    }
+    Ok(output.matches)
+}
+
+// on stderr, out of the way of a diff printed to stdout
+fn print_stats(backend: &dyn SearchBackend, stats: Option<SearchStats>) {
+    if let Some(stats) = stats {
+        eprintln!("{}: {}", backend.name(), stats);
+    }
+}
+
+// the files that couldn't be searched, which the search carried on past
+fn print_errors(errors: &[String]) {
+    for error in errors {
+        eprintln!("warning: {}", error);
+    }
+}
+
+fn print_matches(matched_files: &[MatchedFile]) {
+    for matched_file in matched_files {
+        for line in matched_file.lines() {
+            let marker = match line.kind() {
+                LineKind::Replace => "",
+                LineKind::Insert => "+",
+                LineKind::Delete => "-",
+            };
+            // add one to make line numbers one-indexed
+            print!(
+                "{}:{}{}: {}",
+                matched_file.file_path(),
+                line.num() + 1,
+                marker,
+                line.value()
+            );
+            if !line.value().ends_with('\n') {
+                println!();
+            }
+        }
+    }
+}
+
+fn num_lines(matched_files: &[MatchedFile]) -> usize {
+    matched_files.iter().map(|mf| mf.lines().len()).sum()
+}
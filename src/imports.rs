@@ -0,0 +1,359 @@
+use crate::{
+    matched_file::Line,
+    text::{line_ending, strip_bom},
+};
+
+const PACKAGE: &str = "package ";
+const IMPORT: &str = "import ";
+const STATIC: &str = "static ";
+const AS: &str = " as ";
+const STATIC_IMPORT: &str = "import static ";
+
+/// A parsed `import` declaration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Import<'a> {
+    /// `import static foo.Bar.CONSTANT`
+    pub is_static: bool,
+    /// the imported name, without a trailing `.*`
+    pub path: &'a str,
+    /// `import foo.*`
+    pub wildcard: bool,
+    /// `import foo.Bar as Baz` (kotlin)
+    pub alias: Option<&'a str>,
+}
+
+impl<'a> Import<'a> {
+    /// Whether this imports `fqcn` itself, or one of its members or nested
+    /// types.
+    pub fn imports_from(&self, fqcn: &str) -> bool {
+        match self.path.strip_prefix(fqcn) {
+            Some(rest) => rest.is_empty() || rest.starts_with('.'),
+            None => false,
+        }
+    }
+
+    /// Whether this is a plain `import foo.Bar` of exactly `fqcn`, without
+    /// an alias.
+    pub fn imports_exactly(&self, fqcn: &str) -> bool {
+        !self.is_static && !self.wildcard && self.alias.is_none() && self.path == fqcn
+    }
+}
+
+/// `package foo.bar;` => `foo.bar`
+pub fn parse_package(line: &str) -> Option<&str> {
+    let package = line.strip_prefix(PACKAGE)?.trim();
+    Some(package.strip_suffix(';').unwrap_or(package).trim_end())
+}
+
+/// Parses `import foo.Bar;`, `import static foo.Bar.CONSTANT;`,
+/// `import foo.*` and the like.
+pub fn parse_import(line: &str) -> Option<Import<'_>> {
+    let import = line.strip_prefix(IMPORT)?.trim();
+    let import = import.strip_suffix(';').unwrap_or(import).trim_end();
+
+    let (is_static, import) = match import.strip_prefix(STATIC) {
+        Some(rest) => (true, rest.trim_start()),
+        None => (false, import),
+    };
+    let (import, alias) = match import.split_once(AS) {
+        Some((import, alias)) => (import.trim_end(), Some(alias.trim())),
+        None => (import, None),
+    };
+    let (wildcard, path) = match import.strip_suffix(".*") {
+        Some(rest) => (true, rest),
+        None => (false, import),
+    };
+
+    Some(Import {
+        is_static,
+        path,
+        wildcard,
+        alias,
+    })
+}
+
+/// The package that `contents` declares, if any.
+pub fn file_package(contents: &str) -> Option<&str> {
+    strip_bom(contents)
+        .lines()
+        .find_map(|line| parse_package(line.trim()))
+}
+
+/// The lines to insert into `contents` to add `import`, e.g.
+/// `import foo.Bar;`. See [`insert_imports`].
+pub fn insert_import(contents: &str, import: &str) -> Vec<Line> {
+    insert_imports(contents, &[import])
+}
+
+/// The lines to insert into `contents` to add each of `imports`.
+///
+/// Each import is put in sorted position within the file's (non-static)
+/// imports, or if the file has no imports yet, they're sorted into a block of
+/// their own after the package declaration. New lines end the way the file's
+/// lines do.
+pub fn insert_imports<S: AsRef<str>>(contents: &str, imports: &[S]) -> Vec<Line> {
+    let contents = strip_bom(contents);
+    let newline = line_ending(contents);
+    let mut package_line = None;
+    let mut existing_imports = vec![];
+
+    for (num, line) in contents.lines().enumerate() {
+        let line = line.trim();
+
+        if line.starts_with(PACKAGE) {
+            package_line = Some(num);
+        } else if line.starts_with(IMPORT) {
+            if !line.starts_with(STATIC_IMPORT) {
+                existing_imports.push((num, line));
+            }
+        } else if !line.is_empty() && !is_comment(line) {
+            // reached the body of the file
+            break;
+        }
+    }
+
+    let mut imports = imports
+        .iter()
+        .map(|import| import.as_ref())
+        .collect::<Vec<_>>();
+    imports.sort_by_key(|import| sort_key(import));
+    imports.dedup();
+
+    if let Some(&(last_num, _)) = existing_imports.last() {
+        return imports
+            .iter()
+            .map(|import| {
+                let num = existing_imports
+                    .iter()
+                    .find(|(_, existing)| sort_key(existing) > sort_key(import))
+                    .map_or(last_num + 1, |&(num, _)| num);
+                Line::inserted(num, format!("{}{}", import, newline))
+            })
+            .collect();
+    }
+
+    let num = package_line.map_or(0, |package_line| package_line + 1);
+    let next_is_blank = !matches!(
+        contents.lines().nth(num),
+        Some(line) if !line.trim().is_empty()
+    );
+
+    let mut lines = vec![];
+    if package_line.is_some() {
+        lines.push(Line::inserted(num, newline));
+    }
+    for import in imports {
+        lines.push(Line::inserted(num, format!("{}{}", import, newline)));
+    }
+    if !next_is_blank {
+        lines.push(Line::inserted(num, newline));
+    }
+    lines
+}
+
+// without the trailing `;`, which sorts after `.` and would put
+// `import a.B.C;` before `import a.B;`
+fn sort_key(import: &str) -> &str {
+    let import = import.trim_end();
+    import.strip_suffix(';').unwrap_or(import)
+}
+
+fn is_comment(line: &str) -> bool {
+    line.starts_with("//") || line.starts_with("/*") || line.starts_with('*')
+}
+
+#[cfg(test)]
+mod test {
+    use itertools::assert_equal;
+
+    use super::{file_package, insert_import, insert_imports, parse_import, parse_package, Import};
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Some("foo.bar"), parse_package("package foo.bar;"));
+        assert_eq!(Some("foo.bar"), parse_package("package foo.bar\n"));
+        assert_eq!(None, parse_package("import foo.bar"));
+
+        assert_eq!(
+            Some("foo.Bar"),
+            parse_import("import foo.Bar;\n").map(|import| import.path)
+        );
+        assert_eq!(
+            Some("foo.Bar"),
+            parse_import("import foo.Bar").map(|import| import.path)
+        );
+        assert_eq!(None, parse_import("package foo.bar"));
+
+        assert_eq!(
+            Some("foo.bar"),
+            file_package("// header\npackage foo.bar;\n\nclass Baz {}\n")
+        );
+        assert_eq!(None, file_package("class Baz {}\n"));
+    }
+
+    #[test]
+    fn test_parse_static_and_wildcard() {
+        assert_eq!(
+            Some(Import {
+                is_static: true,
+                path: "foo.Bar.CONSTANT",
+                wildcard: false,
+                alias: None,
+            }),
+            parse_import("import static foo.Bar.CONSTANT;\n")
+        );
+        assert_eq!(
+            Some(Import {
+                is_static: true,
+                path: "foo.Bar",
+                wildcard: true,
+                alias: None,
+            }),
+            parse_import("import static foo.Bar.*;")
+        );
+        assert_eq!(
+            Some(Import {
+                is_static: false,
+                path: "foo",
+                wildcard: true,
+                alias: None,
+            }),
+            parse_import("import foo.*")
+        );
+    }
+
+    #[test]
+    fn test_parse_alias() {
+        assert_eq!(
+            Some(Import {
+                is_static: false,
+                path: "foo.Bar",
+                wildcard: false,
+                alias: Some("Baz"),
+            }),
+            parse_import("import foo.Bar as Baz\n")
+        );
+        assert!(!parse_import("import foo.Bar as Baz")
+            .unwrap()
+            .imports_exactly("foo.Bar"));
+    }
+
+    #[test]
+    fn test_imports_from() {
+        let import = |line| parse_import(line).unwrap();
+
+        assert!(import("import foo.Bar;").imports_from("foo.Bar"));
+        assert!(import("import foo.Bar.Inner;").imports_from("foo.Bar"));
+        assert!(import("import static foo.Bar.CONSTANT;").imports_from("foo.Bar"));
+        assert!(import("import static foo.Bar.*;").imports_from("foo.Bar"));
+        assert!(!import("import foo.BarBaz;").imports_from("foo.Bar"));
+        assert!(!import("import foo.*;").imports_from("foo.Bar"));
+
+        assert!(import("import foo.Bar;").imports_exactly("foo.Bar"));
+        assert!(!import("import foo.Bar.Inner;").imports_exactly("foo.Bar"));
+        assert!(!import("import static foo.Bar.*;").imports_exactly("foo.Bar"));
+    }
+
+    #[test]
+    fn test_insert_sorted() {
+        let contents = "package foo;\n\nimport a.A;\nimport c.C;\n\nclass Foo {}\n";
+        let lines = insert_import(contents, "import b.B;");
+
+        assert_equal(
+            [(3, "import b.B;\n")],
+            lines.iter().map(|line| (line.num(), line.value())),
+        );
+    }
+
+    #[test]
+    fn test_insert_nested() {
+        let contents = "package foo;\n\nimport a.B;\nimport c.C;\n\nclass Foo {}\n";
+        let lines = insert_import(contents, "import a.B.Inner;");
+
+        assert_equal(
+            [(3, "import a.B.Inner;\n")],
+            lines.iter().map(|line| (line.num(), line.value())),
+        );
+
+        let contents = "package foo;\n\nimport a.B.Inner;\n\nclass Foo {}\n";
+        let lines = insert_import(contents, "import a.B;");
+
+        assert_equal(
+            [(2, "import a.B;\n")],
+            lines.iter().map(|line| (line.num(), line.value())),
+        );
+    }
+
+    #[test]
+    fn test_insert_last() {
+        let contents = "package foo\n\nimport a.A\n// comment\nimport static z.Z\n\nclass Foo\n";
+        let lines = insert_import(contents, "import b.B");
+
+        assert_equal(
+            [(3, "import b.B\n")],
+            lines.iter().map(|line| (line.num(), line.value())),
+        );
+    }
+
+    #[test]
+    fn test_insert_after_package() {
+        let contents = "package foo;\n\nclass Foo {}\n";
+        let lines = insert_import(contents, "import b.B;");
+
+        assert_equal(
+            [(1, "\n"), (1, "import b.B;\n")],
+            lines.iter().map(|line| (line.num(), line.value())),
+        );
+
+        let contents = "package foo;\nclass Foo {}\n";
+        let lines = insert_import(contents, "import b.B;");
+
+        assert_equal(
+            [(1, "\n"), (1, "import b.B;\n"), (1, "\n")],
+            lines.iter().map(|line| (line.num(), line.value())),
+        );
+    }
+
+    #[test]
+    fn test_insert_many() {
+        let contents = "package foo;\n\nimport b.B;\n\nclass Foo {}\n";
+        let lines = insert_imports(
+            contents,
+            &["import c.C;", "import a.A;", "import b.B.Inner;"],
+        );
+
+        assert_equal(
+            [
+                (2, "import a.A;\n"),
+                (3, "import b.B.Inner;\n"),
+                (3, "import c.C;\n"),
+            ],
+            lines.iter().map(|line| (line.num(), line.value())),
+        );
+
+        let contents = "package foo;\nclass Foo {}\n";
+        let lines = insert_imports(contents, &["import c.C;", "import a.A;"]);
+
+        assert_equal(
+            [
+                (1, "\n"),
+                (1, "import a.A;\n"),
+                (1, "import c.C;\n"),
+                (1, "\n"),
+            ],
+            lines.iter().map(|line| (line.num(), line.value())),
+        );
+    }
+
+    #[test]
+    fn test_insert_crlf() {
+        let contents = "\u{feff}package foo;\r\nclass Foo {}\r\n";
+        let lines = insert_import(contents, "import b.B;");
+
+        assert_equal(
+            [(1, "\r\n"), (1, "import b.B;\r\n"), (1, "\r\n")],
+            lines.iter().map(|line| (line.num(), line.value())),
+        );
+        assert_eq!(Some("foo"), file_package(contents));
+    }
+}
@@ -0,0 +1,239 @@
+use std::{
+    error::Error,
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    event_log::EventLog, matched_file::file_hash, rename::FileMove, transaction::Transaction,
+};
+
+/// Version of the journal format, bumped whenever it changes incompatibly.
+pub const VERSION: u64 = 1;
+
+/// What an applied rename touched, so that it can be undone. Kept as the
+/// manifest of the rename's [`BackupSet`](crate::backup::BackupSet).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Journal {
+    pub version: u64,
+    pub files: Vec<JournalFile>,
+    #[serde(default)]
+    pub moves: Vec<FileMove>,
+    /// directories created for the moved files, parents first
+    #[serde(default)]
+    pub dirs: Vec<String>,
+}
+
+/// A file rewritten by a rename.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalFile {
+    /// where the file was before it was moved, if it was
+    pub path: String,
+    /// a copy of the file from before the rename
+    pub backup: String,
+    /// hex SHA-256 of the file's contents right after the rename, empty
+    /// until the rename is done
+    #[serde(default)]
+    pub sha256: String,
+}
+
+impl Journal {
+    pub fn read(path: &Path) -> Result<Journal, Box<dyn Error>> {
+        let file = File::open(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        let journal: Journal = serde_json::from_reader(BufReader::new(file))
+            .map_err(|err| format!("{}: {}", path.display(), err))?;
+        if journal.version != VERSION {
+            Err(format!(
+                "{}: journal version {} isn't supported, expected {}",
+                path.display(),
+                journal.version,
+                VERSION
+            ))?;
+        }
+        Ok(journal)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writeln!(writer)?;
+        Ok(())
+    }
+
+    /// Where the file at `path` is now.
+    pub fn moved_to<'a>(&'a self, path: &'a str) -> &'a str {
+        self.moves
+            .iter()
+            .find(|file_move| file_move.from == path)
+            .map(|file_move| file_move.to.as_str())
+            .unwrap_or(path)
+    }
+
+    /// The files that were modified, moved or removed since the rename, or
+    /// whose backups are gone.
+    pub fn changed_files(&self) -> Vec<&str> {
+        self.files
+            .iter()
+            .filter(|file| {
+                let path = self.moved_to(&file.path);
+                !matches!(file_hash(path), Ok(hash) if hash == file.sha256)
+                    || std::fs::metadata(&file.backup).is_err()
+            })
+            .map(|file| self.moved_to(&file.path))
+            .collect()
+    }
+
+    /// Moves files back to where they were, restores their contents from the
+    /// backups, and removes the directories created for the moves if they're
+    /// empty. Either all of it is undone or, if anything fails, none of it.
+    /// Refuses to if any of the files changed since the rename. `base_dir` is the directory the rename searched. Returns the
+    /// number of restored files.
+    pub fn undo(&self, base_dir: &str, events: &mut EventLog) -> Result<usize, Box<dyn Error>> {
+        let changed = self.changed_files();
+        if let Some(path) = changed.first() {
+            Err(format!(
+                "{} files changed since the rename, not undoing it (first: {})",
+                changed.len(),
+                path
+            ))?;
+        }
+
+        let moves = self
+            .moves
+            .iter()
+            .map(|file_move| FileMove {
+                from: file_move.to.clone(),
+                to: file_move.from.clone(),
+            })
+            .collect::<Vec<_>>();
+        let mut transaction = Transaction::new(base_dir);
+        if let Err(err) = self.restore(&mut transaction, &moves, events) {
+            events.error(format!("undo: {}, rolling back", err));
+            transaction.rollback(events)?;
+            Err(format!("{}, nothing was undone", err))?;
+        }
+        // what the undo replaced is the rename, which is no use keeping
+        if let Err(err) = transaction.discard_backups() {
+            events.error(format!("undo: removing its own backups: {}", err));
+        }
+        // children first; anything put in them since is kept, so they're
+        // kept too
+        for dir in self.dirs.iter().rev() {
+            let _ = std::fs::remove_dir(dir);
+        }
+
+        events.info(format!(
+            "undo: restored {} files, moved {} back",
+            self.files.len(),
+            self.moves.len()
+        ));
+        Ok(self.files.len())
+    }
+
+    // moves the files back, then stages and commits their backed up contents
+    fn restore(
+        &self,
+        transaction: &mut Transaction,
+        moves: &[FileMove],
+        events: &mut EventLog,
+    ) -> Result<(), Box<dyn Error>> {
+        transaction.move_files(moves, events)?;
+        for file in self.files.iter() {
+            let contents = std::fs::read(&file.backup)
+                .map_err(|err| format!("reading the backup of {}: {}", file.path, err))?;
+            transaction.stage(&file.path, &contents)?;
+        }
+        transaction.commit()?;
+        for file in self.files.iter() {
+            events.info(format!("undo: restored {}", file.path));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        backup::{BackupSet, BACKUPS_DIR},
+        rename::{FileMove, RenamePlan},
+        search::Search,
+        search_backend::GrepBackend,
+    };
+
+    use super::{Journal, JournalFile, VERSION};
+
+    #[test]
+    fn test_moved_to() {
+        let journal = Journal {
+            version: VERSION,
+            files: vec![JournalFile {
+                path: "com/foo/Bar.java".to_owned(),
+                backup: ".fqn-renamer/backups/1970-01-01T00-00-00Z/files/com/foo/Bar.java"
+                    .to_owned(),
+                sha256: String::new(),
+            }],
+            moves: vec![FileMove {
+                from: "com/foo/Bar.java".to_owned(),
+                to: "net/baz/Quux.java".to_owned(),
+            }],
+            dirs: vec![],
+        };
+
+        assert_eq!("net/baz/Quux.java", journal.moved_to("com/foo/Bar.java"));
+        assert_eq!("com/foo/Other.java", journal.moved_to("com/foo/Other.java"));
+        // neither the file nor its backup exist
+        assert_eq!(vec!["net/baz/Quux.java"], journal.changed_files());
+    }
+
+    #[test]
+    fn test_rename_and_undo() {
+        let dir = std::env::temp_dir().join(format!("fqn-renamer-undo-{}", std::process::id()));
+        let base_dir = dir.to_str().unwrap();
+        std::fs::create_dir_all(dir.join("com/foo")).unwrap();
+        let bar = "package com.foo;\n\npublic class Bar {}\n";
+        let user = "package com.foo;\n\nclass User { Bar b; }\n";
+        std::fs::write(dir.join("com/foo/Bar.java"), bar).unwrap();
+        std::fs::write(dir.join("com/foo/User.java"), user).unwrap();
+
+        let found = Search::new("com.foo.Bar")
+            .run(&GrepBackend, base_dir, &mut Default::default())
+            .unwrap()
+            .matches;
+        let plan = RenamePlan::new("com.foo.Bar", "net.baz.Quux", &found);
+        plan.apply(base_dir, &mut Default::default()).unwrap();
+        assert!(!dir.join("com/foo/Bar.java").exists());
+        assert!(dir.join("net/baz/Quux.java").exists());
+
+        let backups = BackupSet::latest(base_dir).unwrap().unwrap();
+        let journal = backups.journal().unwrap();
+        assert!(journal.changed_files().is_empty());
+        assert_eq!(
+            vec![
+                dir.join("net").to_str().unwrap(),
+                dir.join("net/baz").to_str().unwrap()
+            ],
+            journal.dirs
+        );
+
+        assert_eq!(2, backups.undo(&mut Default::default()).unwrap());
+        assert_eq!(
+            bar,
+            std::fs::read_to_string(dir.join("com/foo/Bar.java")).unwrap()
+        );
+        assert_eq!(
+            user,
+            std::fs::read_to_string(dir.join("com/foo/User.java")).unwrap()
+        );
+        assert!(!dir.join("net").exists());
+        // neither the rename's backups nor the undo's own are left
+        assert!(std::fs::read_dir(dir.join(BACKUPS_DIR))
+            .unwrap()
+            .next()
+            .is_none());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,50 @@
+//! Rename fully qualified names in Java / Kotlin code.
+//!
+//! The pipeline is split into three steps, none of which depend on a
+//! terminal interface:
+//!
+//! 1. [`search::Search`] searches a directory, with `rg` or in-process (see
+//!    [`search_backend::SearchBackend`]), and collects the
+//!    [`matched_file::MatchedFile`]s that reference an identifier, FQCN or
+//!    package
+//! 2. [`rename::RenamePlan`] turns those matches into the replaced lines for
+//!    each file
+//! 3. [`rename::RenamePlan::apply`] writes the replacements back to disk,
+//!    either straight away or after saving the plan for review with
+//!    [`plan_file::PlanFile`]
+//!
+//! ```no_run
+//! use fqn_renamer::{
+//!     event_log::EventLog, rename::RenamePlan, search::Search, search_backend::GrepBackend,
+//! };
+//!
+//! let mut events = EventLog::default();
+//! let found = Search::new("com.foo.Bar")
+//!     .run(&GrepBackend, ".", &mut events)
+//!     .unwrap();
+//! let plan = RenamePlan::new("com.foo.Bar", "net.baz.Quux", &found.matches);
+//! plan.apply(".", &mut events).unwrap();
+//! ```
+
+// tests spell out lines with a single submatch as `vec![start..end]`
+#![cfg_attr(test, allow(clippy::single_range_in_vec_init))]
+
+pub mod backup;
+pub mod diff;
+pub mod event_log;
+pub mod fqcn;
+mod fqcn_processor;
+mod grep_worker;
+pub mod imports;
+pub mod journal;
+pub mod mapping;
+pub mod matched_file;
+pub mod package;
+mod paths;
+pub mod plan_file;
+pub mod rename;
+mod rg_worker;
+pub mod search;
+pub mod search_backend;
+mod text;
+mod transaction;
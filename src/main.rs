@@ -1,24 +1,24 @@
 mod app;
 mod controller;
-mod event_log;
-mod fqcn;
-mod fqcn_processor;
-mod matched_file;
-mod rg_worker;
+mod headless;
 mod scrollable;
 mod ui;
 
 use app::App;
+use clap::{ArgEnum, Parser};
 use controller::AppEvent;
 use crossterm::{
     event::{self, DisableMouseCapture, EnableMouseCapture},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
+use fqn_renamer::{
+    mapping::{read_mappings, Mapping},
+    search_backend::{GrepBackend, RgBackend, SearchBackend},
+};
 use std::{
-    env,
     error::Error,
-    io,
+    io, process,
     sync::mpsc::{channel, Receiver},
     thread,
 };
@@ -27,10 +27,80 @@ use tui::{
     Terminal,
 };
 
+#[derive(Parser)]
+#[clap(
+    version,
+    about = "Rename fully qualified names in Java / Kotlin code",
+    args_conflicts_with_subcommands = true
+)]
+struct Cli {
+    /// Run without the terminal interface
+    #[clap(subcommand)]
+    command: Option<headless::Command>,
+
+    /// Directory to search in the terminal interface
+    #[clap(default_value = ".")]
+    dir: String,
+
+    /// Queue up every pair in a mapping file (plain text, `.csv` or `.json`)
+    /// instead of a single search
+    #[clap(long)]
+    mappings: Option<String>,
+
+    /// Rename each pair in the mapping file from right to left
+    #[clap(long, requires = "mappings")]
+    reverse: bool,
+
+    /// Search in-process, or with the `rg` binary
+    #[clap(long, global = true, arg_enum, default_value = "builtin")]
+    search_backend: Backend,
+}
+
+#[derive(Clone, Copy, ArgEnum)]
+enum Backend {
+    Builtin,
+    Rg,
+}
+
+impl Backend {
+    fn backend(self) -> Box<dyn SearchBackend> {
+        match self {
+            Backend::Builtin => Box::new(GrepBackend),
+            Backend::Rg => Box::new(RgBackend),
+        }
+    }
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
     // main argument parsing
-    let base_dir = env::args().nth(1).unwrap_or_else(|| ".".to_owned());
+    let cli = Cli::parse();
+    let backend = cli.search_backend.backend();
+
+    if let Some(command) = cli.command {
+        if let Err(err) = headless::run(command, backend.as_ref()) {
+            eprintln!("error: {}", err);
+            process::exit(1);
+        }
+        return Ok(());
+    }
 
+    let mappings = match cli.mappings {
+        Some(path) if cli.reverse => read_mappings(&path)?
+            .iter()
+            .map(Mapping::reversed)
+            .collect(),
+        Some(path) => read_mappings(&path)?,
+        None => vec![],
+    };
+
+    run_tui(cli.dir, backend, mappings)
+}
+
+fn run_tui(
+    base_dir: String,
+    backend: Box<dyn SearchBackend>,
+    mappings: Vec<Mapping>,
+) -> Result<(), Box<dyn Error>> {
     // setup terminal
     enable_raw_mode()?;
     let mut stdout = io::stdout();
@@ -44,8 +114,12 @@ fn main() -> Result<(), Box<dyn Error>> {
     // queue up the first redraw of the app
     events_tx.send(AppEvent::Redraw)?;
 
-    let mut app = App::new(base_dir, events_tx.clone());
-    app.search_input_submitted();
+    let mut app = App::new(base_dir, backend, events_tx.clone());
+    if mappings.is_empty() {
+        app.search_input_submitted();
+    } else {
+        app.start_batch(mappings);
+    }
 
     // start polling for user input events
     thread::spawn(move || loop {
@@ -0,0 +1,416 @@
+use std::{error::Error, path::Path};
+
+use serde_json::Value;
+
+use crate::{fqcn::Fqcn, package::Package};
+
+/// One `find -> replace` pair of a batch rename.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mapping {
+    pub find: String,
+    pub replace: String,
+    /// both are binary class names (`foo.Outer$Inner`), which are classes
+    /// even if they don't look like one, e.g. obfuscated names like `a.b`
+    pub binary: bool,
+}
+
+impl Mapping {
+    pub fn new<F: Into<String>, R: Into<String>>(find: F, replace: R) -> Mapping {
+        Mapping {
+            find: find.into(),
+            replace: replace.into(),
+            binary: false,
+        }
+    }
+
+    pub fn binary<F: Into<String>, R: Into<String>>(find: F, replace: R) -> Mapping {
+        Mapping {
+            binary: true,
+            ..Mapping::new(find, replace)
+        }
+    }
+
+    /// The same pair the other way around, e.g. to undo an obfuscation.
+    pub fn reversed(&self) -> Mapping {
+        Mapping {
+            find: self.replace.clone(),
+            replace: self.find.clone(),
+            binary: self.binary,
+        }
+    }
+
+    pub fn find_fqcn(&self) -> Option<Fqcn> {
+        self.fqcn(&self.find)
+    }
+
+    pub fn replace_fqcn(&self) -> Option<Fqcn> {
+        self.fqcn(&self.replace)
+    }
+
+    /// Whether the class would move into, out of, or between outer classes,
+    /// e.g. ProGuard's `com.foo.Bar$Inner -> a.c`, which renaming can't do.
+    pub fn changes_nesting(&self) -> bool {
+        match (self.find_fqcn(), self.replace_fqcn()) {
+            (Some(find), Some(replace)) => find.changes_nesting(&replace),
+            _ => false,
+        }
+    }
+
+    /// Why the pair can't be renamed, if it can't: a binary name that isn't a
+    /// class in a package, e.g. from R8's `-repackageclasses ''`, as nothing
+    /// can import a class in the default package; or a class that would
+    /// change which class it's nested in.
+    pub fn unsupported(&self) -> Option<&'static str> {
+        if self.binary && (self.find_fqcn().is_none() || self.replace_fqcn().is_none()) {
+            return Some(
+                "not a class in a package, classes in the default package can't be imported",
+            );
+        }
+        if self.changes_nesting() {
+            return Some("it changes which class it's nested in, move it by hand");
+        }
+        None
+    }
+
+    fn fqcn(&self, name: &str) -> Option<Fqcn> {
+        if self.binary {
+            Fqcn::from_binary(name)
+        } else {
+            Fqcn::new(name)
+        }
+    }
+
+    // accepts the notations other tools use in their mapping tables, e.g.
+    // `android/support/v4/app/` for a package, or `foo.Outer$Inner` for a
+    // nested class
+    fn normalized(find: &str, replace: &str) -> Mapping {
+        Mapping::new(normalize_name(find), normalize_name(replace))
+    }
+}
+
+/// How a mapping file is laid out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MappingFormat {
+    /// `com.foo.Bar net.baz.Quux`, one pair per line, `#` starts a comment
+    Text,
+    /// `com.foo.Bar,net.baz.Quux`, optionally with a header row, e.g.
+    /// AndroidX's `androidx-class-mapping.csv`, or a table of packages
+    Csv,
+    /// `{"com.foo.Bar": "net.baz.Quux"}`
+    Json,
+    /// a ProGuard / R8 `mapping.txt`, of which only the class lines
+    /// (`com.foo.Bar -> a.b:`) are used
+    ProGuard,
+}
+
+impl MappingFormat {
+    /// Guesses the format from the file extension, defaulting to text.
+    pub fn from_path(path: &str) -> MappingFormat {
+        match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some("csv") => MappingFormat::Csv,
+            Some("json") => MappingFormat::Json,
+            _ => MappingFormat::Text,
+        }
+    }
+}
+
+/// Reads the mappings in the file at `path`.
+pub fn read_mappings(path: &str) -> Result<Vec<Mapping>, Box<dyn Error>> {
+    let contents = std::fs::read_to_string(path)?;
+
+    // ProGuard mappings are usually just called `mapping.txt`
+    let format = match MappingFormat::from_path(path) {
+        MappingFormat::Text if is_proguard(&contents) => MappingFormat::ProGuard,
+        format => format,
+    };
+
+    parse_mappings(&contents, format).map_err(|err| format!("{}: {}", path, err).into())
+}
+
+pub fn parse_mappings(
+    contents: &str,
+    format: MappingFormat,
+) -> Result<Vec<Mapping>, Box<dyn Error>> {
+    let mappings = match format {
+        MappingFormat::Text => parse_text(contents)?,
+        MappingFormat::Csv => parse_csv(contents)?,
+        MappingFormat::Json => parse_json(contents)?,
+        MappingFormat::ProGuard => parse_proguard(contents)?,
+    };
+
+    if mappings.is_empty() {
+        Err("no mappings found")?;
+    }
+    Ok(mappings)
+}
+
+fn parse_text(contents: &str) -> Result<Vec<Mapping>, Box<dyn Error>> {
+    let mut mappings = vec![];
+
+    for (num, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default();
+        let columns = line.split_whitespace().collect::<Vec<_>>();
+        match columns[..] {
+            [] => {}
+            [find, replace] => mappings.push(Mapping::normalized(find, replace)),
+            _ => Err(format!(
+                "line {}: expected `<find> <replace>`, got `{}`",
+                num + 1,
+                line.trim()
+            ))?,
+        }
+    }
+
+    Ok(mappings)
+}
+
+fn parse_csv(contents: &str) -> Result<Vec<Mapping>, Box<dyn Error>> {
+    let mut mappings = vec![];
+
+    for (num, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let columns = line.split(',').map(csv_field).collect::<Vec<_>>();
+        let (find, replace) = match columns[..] {
+            [find, replace] => (find, replace),
+            _ => Err(format!(
+                "line {}: expected `<find>,<replace>`, got `{}`",
+                num + 1,
+                line.trim()
+            ))?,
+        };
+
+        // e.g. `Support Library class,Android X class`
+        if num == 0 && !is_name(&normalize_name(find)) {
+            continue;
+        }
+
+        // classes kept as they are, e.g. AndroidX's `MediaSessionCompat$Callback`
+        let mapping = Mapping::normalized(find, replace);
+        if mapping.find == mapping.replace {
+            continue;
+        }
+        mappings.push(mapping);
+    }
+
+    Ok(mappings)
+}
+
+fn parse_json(contents: &str) -> Result<Vec<Mapping>, Box<dyn Error>> {
+    let value: Value = serde_json::from_str(contents)?;
+    let object = value
+        .as_object()
+        .ok_or("expected an object of `\"<find>\": \"<replace>\"` pairs")?;
+
+    object
+        .iter()
+        .map(|(find, replace)| {
+            let replace = replace
+                .as_str()
+                .ok_or_else(|| format!("`{}`: expected a string, got `{}`", find, replace))?;
+            Ok(Mapping::normalized(find, replace))
+        })
+        .collect()
+}
+
+fn parse_proguard(contents: &str) -> Result<Vec<Mapping>, Box<dyn Error>> {
+    let mut mappings = vec![];
+
+    for (num, line) in contents.lines().enumerate() {
+        // members of the class above are indented
+        if line.trim().is_empty() || line.starts_with('#') || line.starts_with(char::is_whitespace)
+        {
+            continue;
+        }
+
+        let (original, obfuscated) = line
+            .trim_end()
+            .strip_suffix(':')
+            .and_then(|line| line.split_once(" -> "))
+            .ok_or_else(|| {
+                format!(
+                    "line {}: expected `<class> -> <obfuscated class>:`, got `{}`",
+                    num + 1,
+                    line.trim()
+                )
+            })?;
+
+        // classes kept as they are
+        if original.trim() == obfuscated.trim() {
+            continue;
+        }
+
+        mappings.push(Mapping::binary(original.trim(), obfuscated.trim()));
+    }
+
+    Ok(mappings)
+}
+
+// whether the first line that isn't a comment is a ProGuard class line
+fn is_proguard(contents: &str) -> bool {
+    contents
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.ends_with(':') && line.contains(" -> "))
+        .unwrap_or_default()
+}
+
+fn csv_field(field: &str) -> &str {
+    let field = field.trim();
+    field
+        .strip_prefix('"')
+        .and_then(|field| field.strip_suffix('"'))
+        .unwrap_or(field)
+}
+
+fn normalize_name(name: &str) -> String {
+    name.trim_end_matches(&['/', '.'][..])
+        .replace(&['/', '$'][..], ".")
+}
+
+fn is_name(value: &str) -> bool {
+    Fqcn::new(value).is_some() || Package::new(value).is_some()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{is_proguard, parse_mappings, Mapping, MappingFormat};
+
+    #[test]
+    fn test_text() {
+        let mappings = parse_mappings(
+            "# split the foo module\ncom.foo.Bar net.baz.Bar\n\n  com.foo.Quux\tnet.baz.Quux # moved\n",
+            MappingFormat::Text,
+        )
+        .unwrap();
+
+        assert_eq!(
+            vec![
+                Mapping::new("com.foo.Bar", "net.baz.Bar"),
+                Mapping::new("com.foo.Quux", "net.baz.Quux"),
+            ],
+            mappings
+        );
+        assert!(parse_mappings("com.foo.Bar\n", MappingFormat::Text).is_err());
+        assert!(parse_mappings("# nothing\n", MappingFormat::Text).is_err());
+    }
+
+    #[test]
+    fn test_csv() {
+        let mappings = parse_mappings(
+            "Old class,New class\n\"com.foo.Bar\", net.baz.Bar\n",
+            MappingFormat::Csv,
+        )
+        .unwrap();
+
+        assert_eq!(vec![Mapping::new("com.foo.Bar", "net.baz.Bar")], mappings);
+        assert!(parse_mappings("com.foo.Bar,net.baz.Bar,extra\n", MappingFormat::Csv).is_err());
+    }
+
+    #[test]
+    fn test_androidx() {
+        let mappings = parse_mappings(
+            "Support Library class,Android X class\n\
+             android.support.v4.app.Fragment,androidx.fragment.app.Fragment\n\
+             android.support.v4.media.session.MediaSessionCompat$Callback,\
+             android.support.v4.media.session.MediaSessionCompat$Callback\n",
+            MappingFormat::Csv,
+        )
+        .unwrap();
+
+        // the row mapping a class to itself is left out
+        assert_eq!(
+            vec![Mapping::new(
+                "android.support.v4.app.Fragment",
+                "androidx.fragment.app.Fragment"
+            )],
+            mappings
+        );
+
+        let mappings = parse_mappings(
+            "android/support/v4/app/,androidx/fragment/app/\n",
+            MappingFormat::Csv,
+        )
+        .unwrap();
+        assert_eq!(
+            vec![Mapping::new(
+                "android.support.v4.app",
+                "androidx.fragment.app"
+            )],
+            mappings
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let mappings = parse_mappings(
+            r#"{"com.foo.Bar": "net.baz.Bar", "com.foo": "net.foo"}"#,
+            MappingFormat::Json,
+        )
+        .unwrap();
+
+        // in the order the file lists them
+        assert_eq!(
+            vec![
+                Mapping::new("com.foo.Bar", "net.baz.Bar"),
+                Mapping::new("com.foo", "net.foo"),
+            ],
+            mappings
+        );
+        assert!(parse_mappings(r#"{"com.foo.Bar": 1}"#, MappingFormat::Json).is_err());
+        assert!(parse_mappings(r#"["com.foo.Bar"]"#, MappingFormat::Json).is_err());
+    }
+
+    #[test]
+    fn test_proguard() {
+        let contents = "\
+# compiler: R8
+com.foo.Bar -> a.b:
+    int count -> a
+    1:4:void run(java.lang.String) -> b
+com.foo.Bar$Inner -> a.c:
+com.foo.Kept -> com.foo.Kept:
+";
+        assert!(is_proguard(contents));
+        assert!(!is_proguard("com.foo.Bar net.baz.Bar\n"));
+
+        let mappings = parse_mappings(contents, MappingFormat::ProGuard).unwrap();
+        assert_eq!(
+            vec![
+                Mapping::binary("com.foo.Bar", "a.b"),
+                Mapping::binary("com.foo.Bar$Inner", "a.c"),
+            ],
+            mappings
+        );
+
+        let reversed = mappings[1].reversed();
+        assert_eq!("a.c", reversed.find_fqcn().unwrap().value());
+        assert_eq!(
+            "com.foo.Bar.Inner",
+            reversed.replace_fqcn().unwrap().value()
+        );
+
+        assert!(!mappings[0].changes_nesting());
+        assert!(mappings[1].changes_nesting());
+        assert!(reversed.changes_nesting());
+        assert_eq!(None, mappings[0].unsupported());
+        assert!(mappings[1].unsupported().is_some());
+    }
+
+    #[test]
+    fn test_proguard_default_package() {
+        // R8 with `-repackageclasses ''`
+        let mappings = parse_mappings(
+            "com.foo.Bar -> a:\ncom.foo.Quux -> b.c:\n",
+            MappingFormat::ProGuard,
+        )
+        .unwrap();
+
+        assert!(mappings[0].unsupported().is_some());
+        assert!(mappings[0].reversed().unsupported().is_some());
+        assert_eq!(None, mappings[1].unsupported());
+    }
+}
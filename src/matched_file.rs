@@ -1,11 +1,20 @@
 use std::ops::Range;
 
 use itertools::Itertools;
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
-#[derive(Debug, Default, Eq, PartialEq)]
+use crate::text::{decode, strip_bom};
+
+pub use crate::fqcn_processor::FileUsage;
+
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
 pub struct MatchedFile {
     file_path: String,
     lines: Vec<Line>,
+    usage: FileUsage,
+    // hex SHA-256 of the file as it was searched, if known
+    content_hash: Option<String>,
 }
 
 impl MatchedFile {
@@ -13,6 +22,8 @@ impl MatchedFile {
         MatchedFile {
             file_path: file_path.into(),
             lines: lines.into(),
+            usage: Default::default(),
+            content_hash: None,
         }
     }
 
@@ -20,14 +31,90 @@ impl MatchedFile {
         self.file_path.as_str()
     }
 
+    pub fn usage(&self) -> &FileUsage {
+        &self.usage
+    }
+
+    pub fn set_usage(&mut self, usage: FileUsage) {
+        self.usage = usage;
+    }
+
+    pub fn content_hash(&self) -> Option<&str> {
+        self.content_hash.as_deref()
+    }
+
+    pub fn set_content_hash(&mut self, content_hash: Option<String>) {
+        self.content_hash = content_hash;
+    }
+
+    // whether the file changed since it was searched, in a way that its lines
+    // no longer match it; an unchanged hash saves comparing each line
+    pub fn is_stale(&self) -> bool {
+        let contents = match std::fs::read(&self.file_path) {
+            Ok(contents) => contents,
+            Err(_) => return true,
+        };
+        if self.content_hash.as_deref() == Some(hash(&contents).as_str()) {
+            return false;
+        }
+
+        let contents = decode(&contents);
+        let file_lines = strip_bom(&contents)
+            .split_inclusive('\n')
+            .collect::<Vec<_>>();
+        self.lines
+            .iter()
+            .filter(|line| line.kind != LineKind::Insert)
+            .any(|line| file_lines.get(line.num).copied() != Some(line.original()))
+    }
+
+    // keeps lines ordered by line number, with inserted lines coming before
+    // the line they are inserted in front of
+    pub fn insert_line(&mut self, line: Line) {
+        let idx = self.lines.partition_point(|other| {
+            other.num < line.num || (other.num == line.num && other.kind == LineKind::Insert)
+        });
+        self.lines.insert(idx, line);
+    }
+
+    pub fn remove_inserted_lines(&mut self) {
+        self.lines.retain(|line| line.kind != LineKind::Insert);
+    }
+
     pub fn lines_mut(&mut self) -> impl ExactSizeIterator<Item = &mut Line> {
         self.lines.iter_mut()
     }
 
-    pub fn lines(&self) -> impl ExactSizeIterator<Item = &Line> {
+    pub fn lines(&self) -> impl ExactSizeIterator<Item = &Line> + DoubleEndedIterator {
         self.lines.iter()
     }
 
+    // merges the lines of `other`, another set of edits to the same file, into
+    // this one; fails with the number of the first line that both edit in
+    // overlapping places
+    pub fn merge(&mut self, other: &MatchedFile) -> Result<(), usize> {
+        for line in other.lines() {
+            let existing = self.lines.iter_mut().find(|existing| {
+                existing.num == line.num
+                    && if line.kind == LineKind::Insert {
+                        *existing == line
+                    } else {
+                        existing.kind != LineKind::Insert
+                    }
+            });
+
+            match existing {
+                // the same line inserted twice, e.g. a blank separator line
+                Some(_) if line.kind == LineKind::Insert => {}
+                Some(existing) => {
+                    *existing = existing.merge(line).ok_or(line.num)?;
+                }
+                None => self.insert_line(line.clone()),
+            }
+        }
+        Ok(())
+    }
+
     pub fn replace<R: Fn(&str) -> S, S: Into<String>>(&self, replacer: R) -> MatchedFile {
         MatchedFile {
             file_path: self.file_path.clone(),
@@ -36,15 +123,51 @@ impl MatchedFile {
                 .iter()
                 .map(|line| line.replace(&replacer))
                 .collect(),
+            usage: self.usage.clone(),
+            content_hash: self.content_hash.clone(),
         }
     }
 }
 
+/// Hex SHA-256 of the contents of the file at `path`.
+pub(crate) fn file_hash(path: &str) -> std::io::Result<String> {
+    Ok(hash(&std::fs::read(path)?))
+}
+
+pub(crate) fn hash(contents: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(contents))
+}
+
+/// What writing a line back to its file does.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineKind {
+    /// overwrites the line at `num`
+    #[default]
+    Replace,
+    /// inserts a new line in front of the line at `num`
+    Insert,
+    /// removes the line at `num` entirely
+    Delete,
+}
+
+/// Part of a line replaced by [`Line::replace`], relative to the line's
+/// original value.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Edit {
+    pub range: Range<usize>,
+    pub text: String,
+}
+
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Line {
     num: usize,
     value: String,
     submatches: Vec<Range<usize>>,
+    kind: LineKind,
+    // the value before `replace`, and what was replaced in it
+    original: Option<String>,
+    edits: Vec<Edit>,
 }
 
 impl Line {
@@ -53,45 +176,157 @@ impl Line {
             num,
             value: value.into(),
             submatches,
+            kind: LineKind::Replace,
+            original: None,
+            edits: vec![],
         };
         ret.check_invariants();
         ret
     }
 
+    // `original` with each of `edits` applied, highlighting the edited parts
+    fn edited(num: usize, original: String, edits: Vec<Edit>) -> Self {
+        let mut value = String::new();
+        let mut submatches = vec![];
+        let mut pos = 0;
+
+        for edit in edits.iter() {
+            value += &original[pos..edit.range.start];
+            if !edit.text.is_empty() {
+                submatches.push(value.len()..value.len() + edit.text.len());
+                value += &edit.text;
+            }
+            pos = edit.range.end;
+        }
+        value += &original[pos..];
+
+        Line {
+            original: Some(original),
+            edits,
+            ..Line::new(num, value, submatches)
+        }
+    }
+
+    // `original` with `edits` applied, e.g. as read back from a saved plan;
+    // `None` unless the edits are ordered, don't overlap, and fit the line
+    pub fn with_edits<S: Into<String>>(num: usize, original: S, edits: Vec<Edit>) -> Option<Self> {
+        let original = original.into();
+        let mut pos = 0;
+        for edit in edits.iter() {
+            if edit.range.start < pos || original.get(edit.range.clone()).is_none() {
+                return None;
+            }
+            pos = edit.range.end;
+        }
+        Some(Line::edited(num, original, edits))
+    }
+
+    // a new line to insert in front of line `num`, highlighted in its entirety
+    pub fn inserted<S: Into<String>>(num: usize, value: S) -> Self {
+        let value = value.into();
+        let len = value.trim_end_matches(&['\r', '\n'][..]).len();
+        let submatches = if len == 0 {
+            vec![]
+        } else {
+            std::iter::once(0..len).collect()
+        };
+        Self {
+            kind: LineKind::Insert,
+            ..Self::new(num, value, submatches)
+        }
+    }
+
     pub fn num(&self) -> usize {
         self.num
     }
 
+    pub fn kind(&self) -> LineKind {
+        self.kind
+    }
+
     pub fn value(&self) -> &str {
         &self.value
     }
 
+    // the value before it was replaced
+    pub fn original(&self) -> &str {
+        self.original.as_deref().unwrap_or(&self.value)
+    }
+
+    pub fn edits(&self) -> &[Edit] {
+        &self.edits
+    }
+
     pub fn num_submatches(&self) -> usize {
         self.submatches.len()
     }
 
     pub fn replace<R: Fn(&str) -> S, S: Into<String>>(&self, replacer: R) -> Self {
-        let mut new_value = String::new();
-        let mut new_submatches = vec![];
-        let mut pos = 0;
+        let edits = self
+            .submatches
+            .iter()
+            .map(|submatch| Edit {
+                range: submatch.clone(),
+                text: replacer(&self.value[submatch.clone()]).into(),
+            })
+            .collect();
+
+        Line {
+            kind: self.kind,
+            ..Line::edited(self.num, self.value.clone(), edits)
+        }
+    }
 
-        for (is_match, part) in self.iter() {
-            if is_match {
-                let replaced = replacer(part).into();
-                if replaced.is_empty() {
-                    // skip if empty
-                } else {
-                    new_value += &replaced;
-                    new_submatches.push(pos..pos + replaced.len());
-                    pos += replaced.len()
-                }
-            } else {
-                new_value += part;
-                pos += part.len();
-            }
+    // this line with the edits of `other`, another replacement of the same
+    // original line, applied as well; `None` if the edits overlap
+    pub fn merge(&self, other: &Line) -> Option<Self> {
+        if self == other {
+            return Some(self.clone());
+        }
+        if self.original() != other.original() {
+            return None;
         }
 
-        Line::new(self.num, new_value, new_submatches)
+        // edits that leave the text as it was (e.g. a package that only
+        // changes in the declaring file) give way to ones that change it
+        let original = self.original();
+        let mut edits = self
+            .edits
+            .iter()
+            .chain(other.edits.iter())
+            .filter(|edit| original[edit.range.clone()] != edit.text)
+            .cloned()
+            .collect::<Vec<_>>();
+        edits.sort_by_key(|edit| (edit.range.start, edit.range.end));
+        edits.dedup();
+
+        if edits
+            .iter()
+            .tuple_windows()
+            .any(|(a, b)| a.range.end > b.range.start)
+        {
+            return None;
+        }
+
+        let kind = match (self.kind, other.kind) {
+            (LineKind::Delete, LineKind::Delete) => LineKind::Delete,
+            // only an unchanged line can be deleted
+            (LineKind::Delete, _) | (_, LineKind::Delete) if edits.is_empty() => LineKind::Delete,
+            (LineKind::Replace, LineKind::Replace) => LineKind::Replace,
+            _ => return None,
+        };
+
+        let mut merged = Line::edited(self.num, original.to_owned(), edits);
+        merged.kind = kind;
+        Some(merged)
+    }
+
+    // removes the whole line, rather than just its submatches
+    pub fn delete(&self) -> Self {
+        Line {
+            kind: LineKind::Delete,
+            ..self.clone()
+        }
     }
 
     // adjust the range that each submatch covers, e.g. so we can change
@@ -104,7 +339,7 @@ impl Line {
             submatch.end = submatch.start + new_range.len();
 
             // retain only if the submatch isn't empty
-            !submatch.is_empty()
+            submatch.start < submatch.end
         });
         self.check_invariants();
     }
@@ -189,7 +424,7 @@ mod test {
 
     use itertools::assert_equal;
 
-    use super::Line;
+    use super::{file_hash, Line, LineKind, MatchedFile};
 
     #[test]
     fn test_line_iter() {
@@ -312,6 +547,102 @@ mod test {
         assert_equal([(false, "016789")], line.iter().take(100));
     }
 
+    #[test]
+    fn test_delete() {
+        let line = new_line("0123456789", vec![2..6]).delete();
+
+        assert_eq!(LineKind::Delete, line.kind());
+        assert_eq!("0123456789", line.value());
+        assert_eq!(
+            LineKind::Delete,
+            line.replace(|substr| substr.to_owned()).kind()
+        );
+    }
+
+    #[test]
+    fn test_merge() {
+        let original = new_line("Foo foo = new Bar()", vec![0..3, 14..17]);
+        let foo =
+            original.replace(|substr| if substr == "Foo" { "Baz" } else { substr }.to_owned());
+        let bar =
+            original.replace(|substr| if substr == "Bar" { "Quux" } else { substr }.to_owned());
+
+        let merged = foo.merge(&bar).unwrap();
+        assert_eq!("Baz foo = new Quux()", merged.value());
+        assert_equal(
+            [
+                (true, "Baz"),
+                (false, " foo = new "),
+                (true, "Quux"),
+                (false, "()"),
+            ],
+            merged.iter().take(100),
+        );
+
+        let conflicting =
+            original.replace(|substr| if substr == "Foo" { "Smaz" } else { substr }.to_owned());
+        assert_eq!(None, foo.merge(&conflicting));
+        assert_eq!(Some(foo.delete()), foo.delete().merge(&foo.delete()));
+    }
+
+    #[test]
+    fn test_merge_files() {
+        let mut file = MatchedFile::new(
+            "Foo.java",
+            vec![
+                Line::inserted(0, "import a.A;\n"),
+                Line::new(0, "A a;\n", vec![0..1]).replace(|_| "B"),
+            ],
+        );
+        let other = MatchedFile::new(
+            "Foo.java",
+            vec![
+                Line::inserted(0, "import a.A;\n"),
+                Line::new(0, "A a;\n", vec![2..3]).replace(|_| "b"),
+                Line::new(1, "C c;\n", vec![0..1]).replace(|_| "D"),
+            ],
+        );
+
+        file.merge(&other).unwrap();
+        assert_equal(
+            ["import a.A;\n", "B b;\n", "D c;\n"],
+            file.lines().map(|line| line.value()),
+        );
+
+        let conflicting = MatchedFile::new(
+            "Foo.java",
+            vec![Line::new(0, "A a;\n", vec![0..1]).replace(|_| "C")],
+        );
+        assert_eq!(Err(0), file.merge(&conflicting));
+    }
+
+    #[test]
+    fn test_is_stale() {
+        let path = std::env::temp_dir().join(format!("fqn-renamer-stale-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "class Foo {\n  Bar bar;\n}\n").unwrap();
+        let mut file = MatchedFile::new(
+            path,
+            vec![
+                Line::inserted(0, "import a.Bar;\n"),
+                Line::new(1, "  Bar bar;\n", vec![2..5]).replace(|_| "Baz"),
+            ],
+        );
+        file.set_content_hash(file_hash(path).ok());
+        assert!(!file.is_stale());
+
+        // changed elsewhere, the planned lines still match
+        std::fs::write(path, "class Foo {\n  Bar bar;\n}\n// end\n").unwrap();
+        assert!(!file.is_stale());
+
+        // the planned line moved down
+        std::fs::write(path, "class Foo {\n\n  Bar bar;\n}\n").unwrap();
+        assert!(file.is_stale());
+
+        std::fs::remove_file(path).unwrap();
+        assert!(file.is_stale());
+    }
+
     fn new_line(value: &str, matches: Vec<Range<usize>>) -> Line {
         Line::new(0, value, matches)
     }
@@ -0,0 +1,82 @@
+use std::path::PathBuf;
+
+use regex::Regex;
+
+// a package prefix, e.g. `javax.servlet`, covering the package itself and all
+// of its subpackages
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Package {
+    value: String,
+}
+
+impl Package {
+    // at least two segments, so a bare lowercase identifier isn't taken to be
+    // a package
+    pub fn new<S: Into<String>>(value: S) -> Option<Self> {
+        let value = value.into();
+
+        let re = Regex::new(r"^[a-z_][a-z0-9_]*(\.[a-z_][a-z0-9_]*)+$").unwrap();
+        if !re.is_match(&value) {
+            return None;
+        }
+
+        Some(Package { value })
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    // whether `name` (a package or a fully qualified name) is this package,
+    // or something inside of it
+    pub fn contains(&self, name: &str) -> bool {
+        match name.strip_prefix(&self.value) {
+            Some(rest) => rest.is_empty() || rest.starts_with('.'),
+            None => false,
+        }
+    }
+
+    // `name` moved from this package into `to`, e.g. `javax.servlet.http` to
+    // `jakarta.servlet.http`
+    pub fn rename(&self, name: &str, to: &Package) -> Option<String> {
+        if !self.contains(name) {
+            return None;
+        }
+        Some(format!("{}{}", to.value, &name[self.value.len()..]))
+    }
+
+    // directory of `package` relative to the source root, e.g. `foo/bar`
+    pub fn source_dir(package: &str) -> PathBuf {
+        package.split('.').collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::Package;
+
+    #[test]
+    fn test_works() {
+        let package = Package::new("javax.servlet").unwrap();
+        let to = Package::new("jakarta.servlet").unwrap();
+
+        assert!(package.contains("javax.servlet"));
+        assert!(package.contains("javax.servlet.http.HttpServlet"));
+        assert!(!package.contains("javax.servlets"));
+        assert!(!package.contains("javax"));
+
+        assert_eq!(
+            Some("jakarta.servlet.http".to_owned()),
+            package.rename("javax.servlet.http", &to)
+        );
+        assert_eq!(None, package.rename("javax.persistence", &to));
+
+        assert_eq!(Path::new("foo/bar"), Package::source_dir("foo.bar"));
+
+        assert!(Package::new("javax").is_none());
+        assert!(Package::new("javax.servlet.Filter").is_none());
+        assert!(Package::new("javax..servlet").is_none());
+    }
+}
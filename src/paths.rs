@@ -0,0 +1,15 @@
+use std::path::{Component, Path, PathBuf};
+
+/// `path` made absolute against the current directory, without any `.` in
+/// it; not canonicalized, as files moved to aren't there yet.
+pub fn absolute(path: impl AsRef<Path>) -> PathBuf {
+    let path = path.as_ref();
+    let dir = match path.is_absolute() {
+        true => PathBuf::new(),
+        false => std::env::current_dir().unwrap_or_default(),
+    };
+    dir.join(path)
+        .components()
+        .filter(|component| *component != Component::CurDir)
+        .collect()
+}
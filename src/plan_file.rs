@@ -0,0 +1,330 @@
+use std::{
+    error::Error,
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+};
+
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    matched_file::{file_hash, Edit, Line, LineKind, MatchedFile},
+    rename::{FileMove, RenamePlan},
+};
+
+/// Version of the plan file format, bumped whenever it changes incompatibly.
+pub const VERSION: u64 = 1;
+
+/// A [`RenamePlan`] saved as JSON, so it can be reviewed before anything is
+/// written, and applied later.
+///
+/// Paths are the ones `rg` reported, so a plan is applied from the directory
+/// it was made in.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlanFile {
+    pub version: u64,
+    pub files: Vec<PlannedFile>,
+    #[serde(default)]
+    pub moves: Vec<FileMove>,
+}
+
+/// The edits to one file, and what the file looked like when they were
+/// planned.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlannedFile {
+    pub path: String,
+    /// hex SHA-256 of the file's contents
+    pub sha256: String,
+    pub lines: Vec<PlannedLine>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlannedLine {
+    /// one-indexed, as editors and `rg` show it
+    pub line: usize,
+    pub kind: LineKind,
+    /// the line before it's replaced or deleted; absent for inserted lines
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original: Option<String>,
+    /// the line as it will be written
+    pub value: String,
+    /// byte ranges of `original`, and what replaces them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub edits: Vec<PlannedEdit>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlannedEdit {
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+}
+
+impl PlanFile {
+    /// Saves `plan`, along with the hash of each file it edits as it was
+    /// searched, so edits made since then count as changes when applying it.
+    pub fn new(plan: &RenamePlan) -> Result<PlanFile, Box<dyn Error>> {
+        if let Some(conflict) = plan.conflicts.first() {
+            Err(format!(
+                "{} conflicts, not saving the plan (first: {})",
+                plan.conflicts.len(),
+                conflict
+            ))?;
+        }
+
+        let files = plan
+            .replacements
+            .iter()
+            .map(|replacement| {
+                let path = replacement.file_path();
+                let sha256 = match replacement.content_hash() {
+                    Some(hash) => hash.to_owned(),
+                    // its lines still match the file, so it's as it was searched
+                    None if !replacement.is_stale() => {
+                        file_hash(path).map_err(|err| format!("{}: {}", path, err))?
+                    }
+                    None => Err(format!("{}: changed since it was searched", path))?,
+                };
+                Ok(PlannedFile {
+                    path: path.to_owned(),
+                    sha256,
+                    lines: replacement.lines().map(PlannedLine::from_line).collect(),
+                })
+            })
+            .collect::<Result<_, Box<dyn Error>>>()?;
+
+        Ok(PlanFile {
+            version: VERSION,
+            files,
+            moves: plan.moves.clone(),
+        })
+    }
+
+    pub fn read(path: &str) -> Result<PlanFile, Box<dyn Error>> {
+        let value: Value = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+
+        // check the version first, a newer plan might not parse at all
+        match value.get("version").and_then(Value::as_u64) {
+            Some(VERSION) => {}
+            Some(version) => Err(format!(
+                "{}: plan version {} isn't supported, expected {}",
+                path, version, VERSION
+            ))?,
+            None => Err(format!("{}: not a plan file, it has no version", path))?,
+        }
+
+        serde_json::from_value(value).map_err(|err| format!("{}: {}", path, err).into())
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writeln!(writer)?;
+        Ok(())
+    }
+
+    /// The files that were modified or removed since the plan was made.
+    pub fn changed_files(&self) -> Vec<&str> {
+        let edited = self
+            .files
+            .iter()
+            .filter(|file| !matches!(file_hash(&file.path), Ok(hash) if hash == file.sha256))
+            .map(|file| file.path.as_str());
+        let moved = self
+            .moves
+            .iter()
+            .filter(|file_move| std::fs::metadata(&file_move.from).is_err())
+            .map(|file_move| file_move.from.as_str());
+
+        let mut changed = edited.chain(moved).collect::<Vec<_>>();
+        changed.sort_unstable();
+        changed.dedup();
+        changed
+    }
+
+    /// The plan to apply with [`RenamePlan::apply`]. Fails if any of the
+    /// files changed since the plan was made, or if the plan was edited into
+    /// something that can't be applied.
+    pub fn to_plan(&self) -> Result<RenamePlan, Box<dyn Error>> {
+        let changed = self.changed_files();
+        if let Some(path) = changed.first() {
+            Err(format!(
+                "{} files changed since the plan was made, not renaming anything (first: {})",
+                changed.len(),
+                path
+            ))?;
+        }
+
+        let replacements = self
+            .files
+            .iter()
+            .map(|file| {
+                let lines = file
+                    .lines
+                    .iter()
+                    .map(|line| {
+                        line.to_line().ok_or_else(|| {
+                            format!(
+                                "{}:{}: the edits don't add up to the planned line",
+                                file.path, line.line
+                            )
+                        })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                let mut replacement = MatchedFile::new(&file.path, lines);
+                replacement.set_content_hash(Some(file.sha256.clone()));
+                Ok(replacement)
+            })
+            .collect::<Result<_, Box<dyn Error>>>()?;
+
+        Ok(RenamePlan {
+            replacements,
+            moves: self.moves.clone(),
+            ..Default::default()
+        })
+    }
+}
+
+impl PlannedLine {
+    fn from_line(line: &Line) -> PlannedLine {
+        PlannedLine {
+            line: line.num() + 1,
+            kind: line.kind(),
+            original: match line.kind() {
+                LineKind::Insert => None,
+                _ => Some(line.original().to_owned()),
+            },
+            value: line.value().to_owned(),
+            edits: line
+                .edits()
+                .iter()
+                .map(|edit| PlannedEdit {
+                    start: edit.range.start,
+                    end: edit.range.end,
+                    text: edit.text.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    // `None` if `edits` don't turn `original` into `value`
+    fn to_line(&self) -> Option<Line> {
+        let num = self.line.checked_sub(1)?;
+        let original = match (self.kind, &self.original) {
+            (LineKind::Insert, _) => return Some(Line::inserted(num, &self.value)),
+            (_, Some(original)) => original,
+            (_, None) => return None,
+        };
+
+        let edits = self
+            .edits
+            .iter()
+            .map(|edit| Edit {
+                range: edit.start..edit.end,
+                text: edit.text.clone(),
+            })
+            .collect();
+        let line = Line::with_edits(num, original, edits)?;
+
+        match self.kind {
+            LineKind::Delete => Some(line.delete()),
+            LineKind::Replace if line.value() == self.value => Some(line),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use itertools::assert_equal;
+
+    use crate::{
+        matched_file::{file_hash, Line, MatchedFile},
+        rename::RenamePlan,
+    };
+
+    use super::PlanFile;
+
+    #[test]
+    fn test_round_trip() {
+        let path =
+            std::env::temp_dir().join(format!("fqn-renamer-plan-{}.java", std::process::id()));
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "import com.foo.Bar;\nclass Baz {}\n").unwrap();
+
+        let plan = RenamePlan {
+            replacements: vec![MatchedFile::new(
+                path,
+                vec![
+                    Line::inserted(0, "import com.foo.Quux;\n"),
+                    Line::new(0, "import com.foo.Bar;\n", vec![15..18]).replace(|_| "Smaz"),
+                    Line::new(1, "class Baz {}\n", vec![6..9])
+                        .replace(|_| "Quux")
+                        .delete(),
+                ],
+            )],
+            ..Default::default()
+        };
+
+        let plan_file = PlanFile::new(&plan).unwrap();
+        let json = serde_json::to_string_pretty(&plan_file).unwrap();
+        let read: PlanFile = serde_json::from_str(&json).unwrap();
+        assert_eq!(plan_file, read);
+
+        let read_plan = read.to_plan().unwrap();
+        assert_equal(
+            plan.replacements[0]
+                .lines()
+                .map(|line| (line.num(), line.kind(), line.value())),
+            read_plan.replacements[0]
+                .lines()
+                .map(|line| (line.num(), line.kind(), line.value())),
+        );
+
+        // a hand edited line that the edits don't match
+        let mut edited = read.clone();
+        edited.files[0].lines[1].value = "import com.foo.Other;\n".to_owned();
+        assert!(edited.to_plan().is_err());
+
+        std::fs::write(path, "import com.foo.Bar;\nclass Changed {}\n").unwrap();
+        assert_equal([path], read.changed_files());
+        assert!(read.to_plan().is_err());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_changed_after_search() {
+        let path =
+            std::env::temp_dir().join(format!("fqn-renamer-plan-hash-{}.java", std::process::id()));
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "class Bar {}\n").unwrap();
+
+        let mut replacement = MatchedFile::new(
+            path,
+            vec![Line::new(0, "class Bar {}\n", vec![6..9]).replace(|_| "Quux")],
+        );
+        replacement.set_content_hash(Some(file_hash(path).unwrap()));
+        let plan = RenamePlan {
+            replacements: vec![replacement.clone()],
+            ..Default::default()
+        };
+
+        // edited between the search and saving the plan
+        std::fs::write(path, "class Bar {}\nclass Other {}\n").unwrap();
+        let plan_file = PlanFile::new(&plan).unwrap();
+        assert_equal([path], plan_file.changed_files());
+
+        // without a hash, it's only saved if its lines still match
+        replacement.set_content_hash(None);
+        let plan = RenamePlan {
+            replacements: vec![replacement],
+            ..Default::default()
+        };
+        assert!(PlanFile::new(&plan).unwrap().changed_files().is_empty());
+        std::fs::write(path, "class Changed {}\n").unwrap();
+        assert!(PlanFile::new(&plan).is_err());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,1162 @@
+use std::{
+    borrow::Cow,
+    error::Error,
+    fmt::{self, Display},
+    path::{Path, PathBuf},
+};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    event_log::EventLog,
+    fqcn::Fqcn,
+    imports,
+    mapping::Mapping,
+    matched_file::{Line, LineKind, MatchedFile},
+    package::Package,
+    text::{read_text, strip_bom, BOM},
+    transaction::Transaction,
+};
+
+const SOURCE_EXTENSIONS: [&str; 2] = ["java", "kt"];
+const KOTLIN_EXTENSIONS: [&str; 2] = ["kt", "kts"];
+
+/// The replaced lines for every file that a search matched, and the files
+/// that need to move to match their new package.
+#[derive(Debug, Default)]
+pub struct RenamePlan {
+    pub replacements: Vec<MatchedFile>,
+    pub moves: Vec<FileMove>,
+    /// why parts of a batch can't be applied together; a plan with conflicts
+    /// is never applied
+    pub conflicts: Vec<Conflict>,
+}
+
+/// Moving the file that declares a class to its new source path.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileMove {
+    pub from: String,
+    pub to: String,
+}
+
+/// Renames in a batch that contradict each other.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Conflict {
+    /// two renames replace the same part of a line differently
+    Overlap {
+        file_path: String,
+        line_num: usize,
+        line: String,
+    },
+    /// the same name is renamed to two different ones
+    Targets { find: String, replaces: [String; 2] },
+    /// two different names are renamed to the same one
+    Sources { replace: String, finds: [String; 2] },
+    /// a file would be moved to two different places
+    MoveTargets { from: String, to: [String; 2] },
+    /// two different files would be moved to the same place
+    MoveSources { to: String, from: [String; 2] },
+    /// a class would move into, out of, or between outer classes, which
+    /// takes more than renaming it
+    Nesting { find: String, replace: String },
+}
+
+impl Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Conflict::Overlap {
+                file_path,
+                line_num,
+                line,
+            } => write!(
+                f,
+                "{}:{}: conflicting edits to `{}`",
+                file_path,
+                line_num + 1,
+                line.trim_end()
+            ),
+            Conflict::Targets { find, replaces } => write!(
+                f,
+                "{} is renamed to both {} and {}",
+                find, replaces[0], replaces[1]
+            ),
+            Conflict::Sources { replace, finds } => write!(
+                f,
+                "both {} and {} are renamed to {}",
+                finds[0], finds[1], replace
+            ),
+            Conflict::MoveTargets { from, to } => {
+                write!(f, "{} would move to both {} and {}", from, to[0], to[1])
+            }
+            Conflict::MoveSources { to, from } => {
+                write!(f, "both {} and {} would move to {}", from[0], from[1], to)
+            }
+            Conflict::Nesting { find, replace } => write!(
+                f,
+                "renaming {} to {} changes which class it's nested in, move it by hand",
+                find, replace
+            ),
+        }
+    }
+}
+
+impl RenamePlan {
+    /// Plans replacing `find` with `replace` in `found_matches`.
+    ///
+    /// If both are FQCNs, package, identifier and fully qualified usages are
+    /// each rewritten to their counterpart in `replace`. If both are packages,
+    /// everything in `find` and its subpackages moves into `replace`.
+    /// Otherwise every match is replaced with `replace` verbatim (or left
+    /// unchanged if `replace` is empty).
+    pub fn new(find: &str, replace: &str, found_matches: &[MatchedFile]) -> RenamePlan {
+        if let Some(find_fqcn) = Fqcn::new(find) {
+            if let Some(repl_fqcn) = Fqcn::new(replace) {
+                return Self::new_fqcn(&find_fqcn, &repl_fqcn, found_matches);
+            } else {
+                return Self::new_fqcn(&find_fqcn, &find_fqcn, found_matches);
+            }
+        }
+
+        if let Some(find_package) = Package::new(find) {
+            let repl_package = Package::new(replace).unwrap_or_else(|| find_package.clone());
+            return Self::new_package(&find_package, &repl_package, found_matches);
+        }
+
+        // not a valid fqcn, just do a straight identifier replacement
+        let ident = if replace.is_empty() { find } else { replace };
+        RenamePlan {
+            replacements: found_matches
+                .iter()
+                .map(|mf| mf.replace(|_| ident))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Plans renaming one pair of a batch, see [`RenamePlan::new`].
+    pub fn for_mapping(mapping: &Mapping, found_matches: &[MatchedFile]) -> RenamePlan {
+        match (mapping.find_fqcn(), mapping.replace_fqcn()) {
+            (Some(find), Some(repl)) if mapping.binary => {
+                Self::new_fqcn(&find, &repl, found_matches)
+            }
+            // not a class in a package, see `Mapping::unsupported`; renaming
+            // it as a bare identifier would hit every `a`
+            _ if mapping.binary => Default::default(),
+            _ => Self::new(&mapping.find, &mapping.replace, found_matches),
+        }
+    }
+
+    /// Plans a batch of renames, each with the matches of its own search.
+    /// See [`RenamePlan::combine`].
+    pub fn new_batch<'a, I>(batch: I) -> RenamePlan
+    where
+        I: IntoIterator<Item = (&'a Mapping, &'a [MatchedFile])>,
+    {
+        let (mappings, plans): (Vec<_>, Vec<_>) = batch
+            .into_iter()
+            .map(|(mapping, found_matches)| {
+                let plan = Self::for_mapping(mapping, found_matches);
+                (mapping.clone(), plan)
+            })
+            .unzip();
+        Self::combine(&mappings, plans)
+    }
+
+    /// Combines the plans for each of `mappings` into a single plan that
+    /// applies all of them at once.
+    ///
+    /// Every plan must have been made against the same, unmodified files, so
+    /// that swaps (`A -> B`, `B -> A`) and chains (`A -> B`, `B -> C`) each
+    /// rename what was there originally. Renames that contradict each other
+    /// are left out, and recorded as [`Conflict`]s.
+    pub fn combine(mappings: &[Mapping], plans: Vec<RenamePlan>) -> RenamePlan {
+        let mut combined = RenamePlan {
+            conflicts: mapping_conflicts(mappings),
+            ..Default::default()
+        };
+
+        for plan in plans {
+            if let Err(conflict) = combined.merge(plan) {
+                combined.conflicts.push(conflict);
+            }
+        }
+
+        combined
+            .replacements
+            .sort_by(|a, b| a.file_path().cmp(b.file_path()));
+        combined
+    }
+
+    /// Adds the edits and moves of `other` to this plan. Leaves this plan
+    /// unchanged if they conflict with its own.
+    pub fn merge(&mut self, other: RenamePlan) -> Result<(), Conflict> {
+        for file_move in other.moves.iter() {
+            for existing in self.moves.iter() {
+                if existing.from == file_move.from && existing.to != file_move.to {
+                    return Err(Conflict::MoveTargets {
+                        from: file_move.from.clone(),
+                        to: [existing.to.clone(), file_move.to.clone()],
+                    });
+                }
+                if existing.to == file_move.to && existing.from != file_move.from {
+                    return Err(Conflict::MoveSources {
+                        to: file_move.to.clone(),
+                        from: [existing.from.clone(), file_move.from.clone()],
+                    });
+                }
+            }
+        }
+
+        let mut merged = vec![];
+        for replacement in other.replacements {
+            let idx = self
+                .replacements
+                .iter()
+                .position(|existing| existing.file_path() == replacement.file_path());
+            match idx {
+                Some(idx) => {
+                    let mut existing = self.replacements[idx].clone();
+                    existing.merge(&replacement).map_err(|line_num| {
+                        let line = replacement
+                            .lines()
+                            .find(|line| line.num() == line_num)
+                            .map(|line| line.original().to_owned())
+                            .unwrap_or_default();
+                        Conflict::Overlap {
+                            file_path: replacement.file_path().to_owned(),
+                            line_num,
+                            line,
+                        }
+                    })?;
+                    reinsert_imports(&mut existing);
+                    merged.push((Some(idx), existing));
+                }
+                None => merged.push((None, replacement)),
+            }
+        }
+
+        for (idx, replacement) in merged {
+            match idx {
+                Some(idx) => self.replacements[idx] = replacement,
+                None => self.replacements.push(replacement),
+            }
+        }
+        for file_move in other.moves {
+            if self.moved_to(&file_move.from).is_none() {
+                self.moves.push(file_move);
+            }
+        }
+        self.conflicts.extend(other.conflicts);
+
+        Ok(())
+    }
+
+    fn new_fqcn(find: &Fqcn, repl: &Fqcn, found_matches: &[MatchedFile]) -> RenamePlan {
+        // the class itself would have to be cut out of one file and pasted
+        // into another
+        if find.changes_nesting(repl) {
+            return RenamePlan {
+                conflicts: vec![Conflict::Nesting {
+                    find: find.value().to_owned(),
+                    replace: repl.value().to_owned(),
+                }],
+                ..Default::default()
+            };
+        }
+
+        // nested classes are declared in their outer class's file, so only
+        // top-level classes change the package of, or move, their file
+        let top_level = !find.is_nested() && !repl.is_nested();
+        let package_changed = top_level && find.package() != repl.package();
+
+        let binary_name = repl.binary_name();
+        let replacements = found_matches
+            .iter()
+            .map(|mf| {
+                let usage = mf.usage();
+                let mut replacement = mf.replace(|ident| {
+                    if ident == find.ident() {
+                        repl.ident()
+                    } else if ident == find.value() {
+                        repl.value()
+                    } else if ident == find.binary_name() {
+                        binary_name.as_str()
+                    } else if ident == find.class_name() {
+                        repl.class_name()
+                    } else if ident == find.package() {
+                        // only the declaring file changes package, other
+                        // files in the package stay where they are
+                        if usage.declares && package_changed {
+                            repl.package()
+                        } else {
+                            find.package()
+                        }
+                    } else {
+                        unreachable!()
+                    }
+                });
+
+                // files that the class is moving into the package of no
+                // longer need to import it
+                if package_changed && usage.imports && file_package_is(mf.file_path(), repl) {
+                    for line in replacement.lines_mut() {
+                        if matches!(
+                            imports::parse_import(line.value()),
+                            Some(import) if import.imports_exactly(repl.value())
+                        ) {
+                            *line = line.delete();
+                        }
+                    }
+                }
+
+                // files that used the class from within its package, or
+                // through a wildcard import, now need to import it
+                if package_changed
+                    && usage.imports_implicitly()
+                    && !file_package_is(mf.file_path(), repl)
+                {
+                    let import = import_statement(mf.file_path(), repl);
+                    for line in import_lines(mf.file_path(), &import, usage.package_line) {
+                        replacement.insert_line(line);
+                    }
+                }
+
+                replacement
+            })
+            .collect();
+
+        let moves = found_matches
+            .iter()
+            .filter(|mf| top_level && mf.usage().declares)
+            .filter_map(|mf| {
+                let to = moved_path(mf.file_path(), find, repl)?;
+                Some(FileMove {
+                    from: mf.file_path().to_owned(),
+                    to: to.to_string_lossy().into_owned(),
+                })
+            })
+            .collect();
+
+        RenamePlan {
+            replacements,
+            moves,
+            ..Default::default()
+        }
+    }
+
+    fn new_package(find: &Package, repl: &Package, found_matches: &[MatchedFile]) -> RenamePlan {
+        // every match is the package prefix of some fully qualified name
+        let replacements = found_matches
+            .iter()
+            .map(|mf| mf.replace(|_| repl.value()))
+            .collect();
+
+        // files declared in the package or any of its subpackages move along
+        // with it
+        let moves = found_matches
+            .iter()
+            .filter(|mf| mf.usage().declares)
+            .filter_map(|mf| {
+                let file_package = declared_package(mf)?;
+                let file_name = Path::new(mf.file_path()).file_name()?;
+                let to = rebased_path(
+                    mf.file_path(),
+                    &Package::source_dir(file_package).join(file_name),
+                    &Package::source_dir(&find.rename(file_package, repl)?).join(file_name),
+                )?;
+                Some(FileMove {
+                    from: mf.file_path().to_owned(),
+                    to: to.to_string_lossy().into_owned(),
+                })
+            })
+            .collect();
+
+        RenamePlan {
+            replacements,
+            moves,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.replacements.is_empty() && self.moves.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.replacements.clear();
+        self.moves.clear();
+        self.conflicts.clear();
+    }
+
+    /// Where the file at `file_path` will be moved to, if anywhere.
+    pub fn moved_to(&self, file_path: &str) -> Option<&str> {
+        self.moves
+            .iter()
+            .find(|file_move| file_move.from == file_path)
+            .map(|file_move| file_move.to.as_str())
+    }
+
+    /// The files that changed since they were searched, so that the planned
+    /// lines no longer match them.
+    pub fn changed_files(&self) -> Vec<&str> {
+        self.replacements
+            .iter()
+            .filter(|replacement| replacement.is_stale())
+            .map(|replacement| replacement.file_path())
+            .collect()
+    }
+
+    /// Writes the planned replacements to disk, keeping a copy of each
+    /// original file in a [`BackupSet`](crate::backup::BackupSet), then moves
+    /// declaring files to their new source paths.
+    /// Returns the number of replaced matches.
+    ///
+    /// Either every file is renamed, or none are: if any step fails, the
+    /// files already touched are restored, and the error is returned. What
+    /// was renamed is recorded in the manifest of the backups, to undo it
+    /// with. Nothing is renamed if any of the files changed since they were
+    /// searched, see [`RenamePlan::changed_files`]. The backups are kept in
+    /// `base_dir`, the directory that was searched.
+    pub fn apply(&self, base_dir: &str, events: &mut EventLog) -> Result<usize, Box<dyn Error>> {
+        if let Some(conflict) = self.conflicts.first() {
+            Err(format!(
+                "{} conflicts, not renaming anything (first: {})",
+                self.conflicts.len(),
+                conflict
+            ))?;
+        }
+        let changed = self.changed_files();
+        if let Some(path) = changed.first() {
+            Err(format!(
+                "{} files changed since the search, not renaming anything (first: {})",
+                changed.len(),
+                path
+            ))?;
+        }
+
+        let mut transaction = Transaction::new(base_dir);
+        let num_replacements = match self.execute(&mut transaction, events) {
+            Ok(num_replacements) => num_replacements,
+            Err(err) => {
+                events.error(format!("rename: {}, rolling back", err));
+                transaction.rollback(events)?;
+                return Err(format!("{}, nothing was renamed", err).into());
+            }
+        };
+
+        events.info(format!(
+            "rename: replaced {} matches in {} files",
+            num_replacements,
+            self.replacements.len(),
+        ));
+
+        // the rename is done either way, it just can't be undone
+        if let Err(err) = transaction.finish() {
+            events.error(format!("rename: writing the journal for undo: {}", err));
+        }
+
+        Ok(num_replacements)
+    }
+
+    fn execute(
+        &self,
+        transaction: &mut Transaction,
+        events: &mut EventLog,
+    ) -> Result<usize, Box<dyn Error>> {
+        let mut nums = vec![];
+        for replacement in self.replacements.iter() {
+            let (contents, num) = replaced_contents(replacement)?;
+            transaction.stage(replacement.file_path(), &contents)?;
+            nums.push(num);
+        }
+
+        transaction.commit()?;
+        for (replacement, num) in self.replacements.iter().zip(nums.iter()) {
+            events.info(format!(
+                "rename: {} replacements in {}",
+                num,
+                replacement.file_path()
+            ));
+        }
+
+        transaction.move_files(&self.moves, events)?;
+
+        Ok(nums.iter().sum())
+    }
+}
+
+// the contents of the file with the replaced lines written into it, and the
+// number of replaced matches; the lines around them, their line endings, any
+// byte order mark and the file's encoding are kept as they are
+fn replaced_contents(replacement: &MatchedFile) -> Result<(Vec<u8>, usize), Box<dyn Error>> {
+    let file_path = replacement.file_path();
+    let (contents, encoding) =
+        read_text(file_path).map_err(|err| format!("{}: {}", file_path, err))?;
+    // only `\n` ends a line, as it does for the search and `is_stale`
+    let mut lines = strip_bom(&contents)
+        .split_inclusive('\n')
+        .map(Cow::Borrowed)
+        .collect::<Vec<_>>();
+
+    let mut num_replacements = 0;
+
+    // go backwards so inserted lines don't shift the lines still to be written
+    for line in replacement.lines().rev() {
+        num_replacements += line.num_submatches();
+
+        let num = line.num();
+        let past_end = match line.kind() {
+            LineKind::Insert => num > lines.len(),
+            LineKind::Replace | LineKind::Delete => num >= lines.len(),
+        };
+        if past_end {
+            Err(format!(
+                "{}: line {} is past the end of the file",
+                file_path,
+                num + 1
+            ))?;
+        }
+        match line.kind() {
+            LineKind::Replace => lines[num] = Cow::Borrowed(line.value()),
+            LineKind::Insert => lines.insert(num, Cow::Borrowed(line.value())),
+            LineKind::Delete => {
+                lines.remove(num);
+            }
+        }
+    }
+
+    // the matched lines don't include it, put it back in front
+    let mut replaced = String::with_capacity(contents.len());
+    if contents.starts_with(BOM) {
+        replaced.push(BOM);
+    }
+    replaced.extend(lines);
+    let contents = replaced;
+    // only Latin-1 can fail, on names it has no characters for
+    let contents = encoding.encode(&contents).ok_or_else(|| {
+        format!(
+            "{}: the renamed lines don't fit the file's Latin-1 encoding",
+            file_path
+        )
+    })?;
+    Ok((contents.into_owned(), num_replacements))
+}
+
+// the same name renamed to different ones, or different names renamed to the
+// same one
+fn mapping_conflicts(mappings: &[Mapping]) -> Vec<Conflict> {
+    let mut conflicts = vec![];
+
+    for (idx, a) in mappings.iter().enumerate() {
+        for b in mappings[idx + 1..].iter() {
+            if a.find == b.find && a.replace != b.replace {
+                conflicts.push(Conflict::Targets {
+                    find: a.find.clone(),
+                    replaces: [a.replace.clone(), b.replace.clone()],
+                });
+            } else if a.replace == b.replace && a.find != b.find {
+                conflicts.push(Conflict::Sources {
+                    replace: a.replace.clone(),
+                    finds: [a.find.clone(), b.find.clone()],
+                });
+            }
+        }
+    }
+
+    conflicts
+}
+
+// `import foo.Bar;` for java, or `import foo.Bar` for kotlin
+fn import_statement(file_path: &str, fqcn: &Fqcn) -> String {
+    let extension = Path::new(file_path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default();
+
+    if KOTLIN_EXTENSIONS.contains(&extension) {
+        format!("import {}", fqcn.value())
+    } else {
+        format!("import {};", fqcn.value())
+    }
+}
+
+// whether the file at `file_path` is in the package of `fqcn`
+fn file_package_is(file_path: &str, fqcn: &Fqcn) -> bool {
+    match read_text(file_path) {
+        Ok((contents, _)) => imports::file_package(&contents) == Some(fqcn.package()),
+        Err(_) => false,
+    }
+}
+
+// the lines adding `import` to the file, in the right place in its import
+// block; if the file can't be read, the import goes right after the package
+// declaration
+fn import_lines(file_path: &str, import: &str, package_line: Option<usize>) -> Vec<Line> {
+    match (read_text(file_path), package_line) {
+        (Ok((contents, _)), _) => imports::insert_import(&contents, import),
+        (Err(_), Some(package_line)) => vec![
+            Line::inserted(package_line + 1, "\n"),
+            Line::inserted(package_line + 1, format!("{}\n", import)),
+        ],
+        (Err(_), None) => vec![],
+    }
+}
+
+// imports added to the same file by different renames of a batch, inserted
+// again all at once, so that they're sorted among each other and share the
+// blank lines around a new import block
+fn reinsert_imports(replacement: &mut MatchedFile) {
+    let added = replacement
+        .lines()
+        .filter(|line| line.kind() == LineKind::Insert)
+        .filter(|line| imports::parse_import(line.value()).is_some())
+        .map(|line| line.value().trim_end().to_owned())
+        .collect::<Vec<_>>();
+    if added.len() < 2 {
+        return;
+    }
+    let contents = match read_text(replacement.file_path()) {
+        Ok((contents, _)) => contents,
+        Err(_) => return,
+    };
+
+    replacement.remove_inserted_lines();
+    for line in imports::insert_imports(&contents, &added) {
+        replacement.insert_line(line);
+    }
+}
+
+// if `file_path` is where `find` is declared, e.g. `src/main/java/foo/Bar.java`,
+// the path that `repl` should be declared at under the same source root
+fn moved_path(file_path: &str, find: &Fqcn, repl: &Fqcn) -> Option<PathBuf> {
+    let path = Path::new(file_path);
+    let extension = path.extension()?.to_str()?;
+    if !SOURCE_EXTENSIONS.contains(&extension) {
+        return None;
+    }
+
+    rebased_path(
+        file_path,
+        &find.source_path(extension),
+        &repl.source_path(extension),
+    )
+}
+
+// if `file_path` ends with `from`, e.g. `src/main/java/foo/Bar.java` and
+// `foo/Bar.java`, the path ending with `to` instead under the same source root
+fn rebased_path(file_path: &str, from: &Path, to: &Path) -> Option<PathBuf> {
+    let path = Path::new(file_path);
+    if !path.ends_with(from) {
+        return None;
+    }
+
+    let source_root = path.ancestors().nth(from.components().count())?;
+    let to = source_root.join(to);
+    if to == path {
+        return None;
+    }
+
+    Some(to)
+}
+
+// the package that a file in a package search declares, from its matched
+// `package` line
+fn declared_package(matched_file: &MatchedFile) -> Option<&str> {
+    let package_line = matched_file.usage().package_line?;
+    matched_file
+        .lines()
+        .find(|line| line.num() == package_line)
+        .and_then(|line| imports::parse_package(line.value()))
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use itertools::assert_equal;
+
+    use crate::{
+        backup::BackupSet,
+        fqcn::Fqcn,
+        fqcn_processor::{process_matched_file_fqcn, process_matched_file_package},
+        mapping::Mapping,
+        matched_file::{Line, MatchedFile},
+        package::Package,
+        search::Search,
+        search_backend::GrepBackend,
+    };
+
+    use super::{moved_path, replaced_contents, Conflict, RenamePlan};
+
+    #[test]
+    fn test_only_declaring_file_changes_package() {
+        let find = Fqcn::new("com.foo.Bar").unwrap();
+        let found = process_matched_file_fqcn(
+            &find,
+            vec![
+                MatchedFile::new(
+                    "com/foo/Bar.java",
+                    vec![
+                        Line::new(0, "package com.foo;\n", vec![0..16]),
+                        Line::new(2, "class Bar {}\n", vec![6..9]),
+                    ],
+                ),
+                MatchedFile::new(
+                    "com/foo/Sibling.kt",
+                    vec![
+                        Line::new(0, "package com.foo\n", vec![0..15]),
+                        Line::new(3, "val bar: Bar\n", vec![9..12]),
+                    ],
+                ),
+            ],
+        );
+
+        let plan = RenamePlan::new("com.foo.Bar", "net.baz.Quux", &found);
+        assert_equal(
+            ["package net.baz;\n", "class Quux {}\n"],
+            plan.replacements[0].lines().map(|line| line.value()),
+        );
+        assert_equal(
+            [
+                "package com.foo\n",
+                "\n",
+                "import net.baz.Quux\n",
+                "val bar: Quux\n",
+            ],
+            plan.replacements[1].lines().map(|line| line.value()),
+        );
+
+        assert_eq!(1, plan.moves.len());
+        assert_eq!("com/foo/Bar.java", plan.moves[0].from);
+        assert_eq!(Some("net/baz/Quux.java"), plan.moved_to("com/foo/Bar.java"));
+    }
+
+    #[test]
+    fn test_nested_class() {
+        let find = Fqcn::new("com.foo.Outer.Inner").unwrap();
+        let found = process_matched_file_fqcn(
+            &find,
+            vec![
+                MatchedFile::new(
+                    "com/foo/Outer.java",
+                    vec![
+                        Line::new(0, "package com.foo;\n", vec![0..16]),
+                        Line::new(2, "public class Outer {\n", vec![7..18]),
+                        Line::new(4, "  static class Inner {}\n", vec![15..20]),
+                    ],
+                ),
+                MatchedFile::new(
+                    "com/other/User.java",
+                    vec![
+                        Line::new(2, "import com.foo.Outer;\n", vec![0..21]),
+                        Line::new(4, "  Outer.Inner inner;\n", vec![2..13]),
+                        Line::new(5, "  // com.foo.Outer$Inner\n", vec![5..24]),
+                    ],
+                ),
+            ],
+        );
+
+        let plan = RenamePlan::new("com.foo.Outer.Inner", "com.foo.Outer.Renamed", &found);
+        assert_equal(
+            [
+                "package com.foo;\n",
+                "public class Outer {\n",
+                "  static class Renamed {}\n",
+            ],
+            plan.replacements[0].lines().map(|line| line.value()),
+        );
+        assert_equal(
+            [
+                "import com.foo.Outer;\n",
+                "  Outer.Renamed inner;\n",
+                "  // com.foo.Outer$Renamed\n",
+            ],
+            plan.replacements[1].lines().map(|line| line.value()),
+        );
+        assert!(plan.moves.is_empty());
+    }
+
+    #[test]
+    fn test_nesting_changed() {
+        let find = Fqcn::new("com.foo.Outer.Inner").unwrap();
+        let found = process_matched_file_fqcn(
+            &find,
+            vec![MatchedFile::new(
+                "com/other/User.java",
+                vec![Line::new(2, "import com.foo.Outer.Inner;\n", vec![0..27])],
+            )],
+        );
+
+        let plan = RenamePlan::new("com.foo.Outer.Inner", "com.foo.Inner", &found);
+        assert!(plan.is_empty());
+        assert_eq!(
+            vec![Conflict::Nesting {
+                find: "com.foo.Outer.Inner".to_owned(),
+                replace: "com.foo.Inner".to_owned(),
+            }],
+            plan.conflicts
+        );
+        assert!(plan.apply(".", &mut Default::default()).is_err());
+    }
+
+    #[test]
+    fn test_package() {
+        let find = Package::new("javax.servlet").unwrap();
+        let found = process_matched_file_package(
+            &find,
+            vec![
+                MatchedFile::new(
+                    "src/main/java/javax/servlet/http/HttpServlet.java",
+                    vec![
+                        Line::new(0, "package javax.servlet.http;\n", vec![7..21]),
+                        Line::new(2, "import javax.servlet.Filter;\n", vec![6..20]),
+                    ],
+                ),
+                MatchedFile::new(
+                    "src/main/java/com/foo/Usage.kt",
+                    vec![
+                        Line::new(0, "package com.foo\n", vec![]),
+                        Line::new(2, "import javax.servlet.http.*\n", vec![6..20]),
+                    ],
+                ),
+            ],
+        );
+
+        let plan = RenamePlan::new("javax.servlet", "jakarta.servlet", &found);
+        assert_equal(
+            [
+                "package jakarta.servlet.http;\n",
+                "import jakarta.servlet.Filter;\n",
+            ],
+            plan.replacements[0].lines().map(|line| line.value()),
+        );
+        assert_equal(
+            ["package com.foo\n", "import jakarta.servlet.http.*\n"],
+            plan.replacements[1].lines().map(|line| line.value()),
+        );
+
+        assert_eq!(1, plan.moves.len());
+        assert_eq!(
+            Some("src/main/java/jakarta/servlet/http/HttpServlet.java"),
+            plan.moved_to("src/main/java/javax/servlet/http/HttpServlet.java")
+        );
+    }
+
+    #[test]
+    fn test_batch() {
+        // what searching for each class finds in the same file
+        let file = |submatch| {
+            MatchedFile::new(
+                "com/foo/User.java",
+                vec![
+                    Line::new(0, "package com.foo;\n", vec![0..16]),
+                    Line::new(2, "  Bar bar = new Baz();\n", vec![submatch]),
+                ],
+            )
+        };
+        let bar = Mapping::new("com.foo.Bar", "com.foo.Bar2");
+        let bar_found = process_matched_file_fqcn(&Fqcn::new(&bar.find).unwrap(), vec![file(2..5)]);
+        let baz = Mapping::new("com.foo.Baz", "com.foo.Baz2");
+        let baz_found =
+            process_matched_file_fqcn(&Fqcn::new(&baz.find).unwrap(), vec![file(16..19)]);
+
+        let plan =
+            RenamePlan::new_batch([(&bar, bar_found.as_slice()), (&baz, baz_found.as_slice())]);
+
+        assert_eq!(1, plan.replacements.len());
+        assert_equal(
+            ["package com.foo;\n", "  Bar2 bar = new Baz2();\n"],
+            plan.replacements[0].lines().map(|line| line.value()),
+        );
+    }
+
+    #[test]
+    fn test_batch_swap() {
+        // `A` and `B` swap names, and each refers to the other
+        let a = Mapping::new("com.foo.A", "com.foo.B");
+        let a_found = process_matched_file_fqcn(
+            &Fqcn::new(&a.find).unwrap(),
+            vec![
+                MatchedFile::new(
+                    "com/foo/A.java",
+                    vec![
+                        Line::new(0, "package com.foo;\n", vec![0..16]),
+                        Line::new(2, "class A { B b; }\n", vec![6..7]),
+                    ],
+                ),
+                MatchedFile::new(
+                    "com/foo/B.java",
+                    vec![
+                        Line::new(0, "package com.foo;\n", vec![0..16]),
+                        Line::new(2, "class B { A a; }\n", vec![10..11]),
+                    ],
+                ),
+            ],
+        );
+        let b = Mapping::new("com.foo.B", "com.foo.A");
+        let b_found = process_matched_file_fqcn(
+            &Fqcn::new(&b.find).unwrap(),
+            vec![
+                MatchedFile::new(
+                    "com/foo/A.java",
+                    vec![
+                        Line::new(0, "package com.foo;\n", vec![0..16]),
+                        Line::new(2, "class A { B b; }\n", vec![10..11]),
+                    ],
+                ),
+                MatchedFile::new(
+                    "com/foo/B.java",
+                    vec![
+                        Line::new(0, "package com.foo;\n", vec![0..16]),
+                        Line::new(2, "class B { A a; }\n", vec![6..7]),
+                    ],
+                ),
+            ],
+        );
+
+        let plan = RenamePlan::new_batch([(&a, a_found.as_slice()), (&b, b_found.as_slice())]);
+        assert_eq!(Vec::<Conflict>::new(), plan.conflicts);
+        assert_equal(
+            ["package com.foo;\n", "class B { A b; }\n"],
+            plan.replacements[0].lines().map(|line| line.value()),
+        );
+        assert_equal(
+            ["package com.foo;\n", "class A { B a; }\n"],
+            plan.replacements[1].lines().map(|line| line.value()),
+        );
+        assert_eq!(Some("com/foo/B.java"), plan.moved_to("com/foo/A.java"));
+        assert_eq!(Some("com/foo/A.java"), plan.moved_to("com/foo/B.java"));
+    }
+
+    #[test]
+    fn test_batch_imports() {
+        let path = std::env::temp_dir().join(format!("fqn-renamer-imports-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "package com.foo;\nclass User { A a; B b; }\n").unwrap();
+
+        // both classes leave the package, so the file now has to import each
+        let file = |submatch| {
+            MatchedFile::new(
+                path,
+                vec![
+                    Line::new(0, "package com.foo;\n", vec![0..16]),
+                    Line::new(1, "class User { A a; B b; }\n", vec![submatch]),
+                ],
+            )
+        };
+        let a = Mapping::new("com.foo.A", "net.z.A");
+        let a_found = process_matched_file_fqcn(&Fqcn::new(&a.find).unwrap(), vec![file(13..14)]);
+        let b = Mapping::new("com.foo.B", "net.a.B");
+        let b_found = process_matched_file_fqcn(&Fqcn::new(&b.find).unwrap(), vec![file(18..19)]);
+
+        let plan = RenamePlan::new_batch([(&a, a_found.as_slice()), (&b, b_found.as_slice())]);
+        assert_eq!(Vec::<Conflict>::new(), plan.conflicts);
+        assert_equal(
+            [
+                "package com.foo;\n",
+                "\n",
+                "import net.a.B;\n",
+                "import net.z.A;\n",
+                "\n",
+                "class User { A a; B b; }\n",
+            ],
+            plan.replacements[0].lines().map(|line| line.value()),
+        );
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_batch_conflicts() {
+        let found = |find: &str| {
+            process_matched_file_fqcn(
+                &Fqcn::new(find).unwrap(),
+                vec![MatchedFile::new(
+                    "User.java",
+                    vec![Line::new(2, "import com.foo.A;\n", vec![7..16])],
+                )],
+            )
+        };
+        let to_b = Mapping::new("com.foo.A", "com.foo.B");
+        let to_c = Mapping::new("com.foo.A", "com.foo.C");
+        let found_a = found("com.foo.A");
+
+        let plan =
+            RenamePlan::new_batch([(&to_b, found_a.as_slice()), (&to_c, found_a.as_slice())]);
+        assert_eq!(
+            vec![
+                Conflict::Targets {
+                    find: "com.foo.A".to_owned(),
+                    replaces: ["com.foo.B".to_owned(), "com.foo.C".to_owned()],
+                },
+                Conflict::Overlap {
+                    file_path: "User.java".to_owned(),
+                    line_num: 2,
+                    line: "import com.foo.A;\n".to_owned(),
+                },
+            ],
+            plan.conflicts
+        );
+        // the first rename is kept, the conflicting one left out
+        assert_equal(
+            ["import com.foo.B;\n"],
+            plan.replacements[0].lines().map(|line| line.value()),
+        );
+        assert!(plan.apply(".", &mut Default::default()).is_err());
+
+        let from_a = Mapping::new("com.foo.A", "com.foo.C");
+        let from_b = Mapping::new("com.foo.B", "com.foo.C");
+        let plan = RenamePlan::combine(&[from_a, from_b], vec![]);
+        assert_eq!(
+            vec![Conflict::Sources {
+                replace: "com.foo.C".to_owned(),
+                finds: ["com.foo.A".to_owned(), "com.foo.B".to_owned()],
+            }],
+            plan.conflicts
+        );
+    }
+
+    #[test]
+    fn test_obfuscated() {
+        let mapping = Mapping::binary("com.foo.Bar", "a.b").reversed();
+        let found = process_matched_file_fqcn(
+            &mapping.find_fqcn().unwrap(),
+            vec![MatchedFile::new(
+                "a/b.java",
+                vec![
+                    Line::new(0, "package a;\n", vec![0..10]),
+                    Line::new(2, "class b {}\n", vec![6..7]),
+                ],
+            )],
+        );
+
+        let plan = RenamePlan::for_mapping(&mapping, &found);
+        assert_equal(
+            ["package com.foo;\n", "class Bar {}\n"],
+            plan.replacements[0].lines().map(|line| line.value()),
+        );
+        assert_eq!(Some("com/foo/Bar.java"), plan.moved_to("a/b.java"));
+    }
+
+    #[test]
+    fn test_replaced_contents() {
+        let path = std::env::temp_dir().join(format!("fqn-renamer-crlf-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "\u{feff}package com.foo;\r\n\r\nclass Bar {}\r\n").unwrap();
+
+        // as `rg` reports them, without the byte order mark
+        let replacement = MatchedFile::new(
+            path,
+            vec![
+                Line::new(0, "package com.foo;\r\n", vec![8..15]).replace(|_| "net.baz"),
+                Line::inserted(1, "\r\n"),
+                Line::inserted(1, "import a.A;\r\n"),
+                Line::new(2, "class Bar {}\r\n", vec![6..9]).replace(|_| "Quux"),
+            ],
+        );
+        let (contents, num_replacements) = replaced_contents(&replacement).unwrap();
+        assert_eq!(
+            "\u{feff}package net.baz;\r\n\r\nimport a.A;\r\n\r\nclass Quux {}\r\n".as_bytes(),
+            contents
+        );
+        assert_eq!(3, num_replacements);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_replaced_contents_line_breaks() {
+        let path = std::env::temp_dir().join(format!("fqn-renamer-breaks-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let replacement = MatchedFile::new(
+            path,
+            vec![Line::new(1, "class User { Bar b; }\n", vec![13..16]).replace(|_| "Quux")],
+        );
+
+        // only `\n` ends a line, not a form feed, nor a lone `\r`
+        std::fs::write(path, "// page\x0cbreak\rand\nclass User { Bar b; }\n").unwrap();
+        assert_eq!(
+            &b"// page\x0cbreak\rand\nclass User { Quux b; }\n"[..],
+            replaced_contents(&replacement).unwrap().0
+        );
+
+        // nor U+0085, which is what Latin-1 decodes 0x85 to
+        std::fs::write(path, b"// caf\x85\nclass User { Bar b; }\n").unwrap();
+        assert_eq!(
+            &b"// caf\x85\nclass User { Quux b; }\n"[..],
+            replaced_contents(&replacement).unwrap().0
+        );
+
+        // a file that lost lines since it was searched
+        std::fs::write(path, "class User {}\n").unwrap();
+        assert!(replaced_contents(&replacement).is_err());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_apply_latin1() {
+        let dir = std::env::temp_dir().join(format!("fqn-renamer-latin1-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("User.java");
+        let path = path.to_str().unwrap();
+        let original = b"package net.baz;\n\n// caf\xe9\nimport com.foo.Bar;\n\nclass User { Bar b; } // \xe9t\xe9\n";
+        std::fs::write(path, original).unwrap();
+
+        let found = Search::new("com.foo.Bar")
+            .run(&GrepBackend, dir.to_str().unwrap(), &mut Default::default())
+            .unwrap()
+            .matches;
+        let plan = RenamePlan::new("com.foo.Bar", "com.foo.Quux", &found);
+        assert_eq!(
+            2,
+            plan.apply(dir.to_str().unwrap(), &mut Default::default())
+                .unwrap()
+        );
+        assert_eq!(
+            &b"package net.baz;\n\n// caf\xe9\nimport com.foo.Quux;\n\nclass User { Quux b; } // \xe9t\xe9\n"[..],
+            std::fs::read(path).unwrap()
+        );
+
+        // and back, from the backup it took
+        let backups = BackupSet::latest(dir.to_str().unwrap()).unwrap().unwrap();
+        backups.undo(&mut Default::default()).unwrap();
+        assert_eq!(&original[..], std::fs::read(path).unwrap());
+
+        // a name that Latin-1 has no characters for
+        let replacement = MatchedFile::new(
+            path,
+            vec![
+                Line::new(5, "class User { Bar b; } // \u{e9}t\u{e9}\n", vec![13..16])
+                    .replace(|_| "\u{411}ar"),
+            ],
+        );
+        assert!(replaced_contents(&replacement).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_moved_path() {
+        let find = Fqcn::new("com.foo.Bar").unwrap();
+        let repl = Fqcn::new("net.baz.Quux").unwrap();
+
+        assert_eq!(
+            Some(PathBuf::from("src/main/java/net/baz/Quux.java")),
+            moved_path("src/main/java/com/foo/Bar.java", &find, &repl)
+        );
+        assert_eq!(
+            Some(PathBuf::from("net/baz/Quux.kt")),
+            moved_path("com/foo/Bar.kt", &find, &repl)
+        );
+
+        // not the declaring file
+        assert_eq!(None, moved_path("src/com/foo/Other.java", &find, &repl));
+        // not under the package directory
+        assert_eq!(None, moved_path("src/Bar.java", &find, &repl));
+        // not a source file
+        assert_eq!(None, moved_path("src/com/foo/Bar.txt", &find, &repl));
+        // not actually moving
+        assert_eq!(None, moved_path("com/foo/Bar.java", &find, &find));
+    }
+}
@@ -1,38 +1,49 @@
-use std::io::Read;
+use std::ffi::OsStr;
+use std::io::{BufRead, BufReader, Read};
 use std::mem;
-use std::ops::Range;
-use std::sync::mpsc::Sender;
 use std::sync::Arc;
+use std::time::Duration;
 use std::{
     error::Error,
-    process::{Child, ChildStdout, Command, Stdio},
+    process::{Child, Command, ExitStatus, Stdio},
     thread::{self, JoinHandle},
 };
 
+use base64::{engine::general_purpose::STANDARD, Engine};
 use parking_lot::{Mutex, MutexGuard};
 use serde_json::Value;
 
-use crate::controller::AppEvent;
 use crate::event_log::EventLog;
-use crate::matched_file::{Line, MatchedFile};
+use crate::matched_file::{hash, Line, MatchedFile};
+use crate::search_backend::SearchStats;
+use crate::text::{decode, Encoding};
 
+/// A running `rg --json` process, and the threads collecting its matches and
+/// errors.
 pub struct RgWorker {
     name: String,
-    pid: u32,
     process: Child,
+    events: EventLog,
     thread: Option<JoinHandle<()>>,
+    stderr_thread: Option<JoinHandle<()>>,
     results: Arc<Mutex<Vec<MatchedFile>>>,
+    stats: Arc<Mutex<Option<SearchStats>>>,
+    errors: Arc<Mutex<Vec<String>>>,
 }
 
 impl RgWorker {
-    pub fn new<S>(
-        events_sender: Sender<AppEvent>,
+    /// Spawns `rg` with `args`, calling `notify` each time a matched file has
+    /// been added to [`RgWorker::results`].
+    pub fn new<N, S, A>(
+        notify: N,
         name: S,
         events: EventLog,
-        args: &[&str],
+        args: &[A],
     ) -> Result<RgWorker, Box<dyn Error>>
     where
+        N: Fn() + Send + 'static,
         S: Into<String>,
+        A: AsRef<OsStr>,
     {
         let name = name.into();
         let mut process = Command::new("rg")
@@ -42,185 +53,526 @@ impl RgWorker {
             .spawn()?;
 
         let results: Arc<Mutex<Vec<MatchedFile>>> = Default::default();
-        let pid = process.id();
+        let stats: Arc<Mutex<Option<SearchStats>>> = Default::default();
+        let errors: Arc<Mutex<Vec<String>>> = Default::default();
         let child_stdout = process.stdout.take().unwrap();
         let thread = thread::spawn(Self::worker_impl_factory(
-            events_sender,
+            Box::new(notify),
             name.clone(),
-            events,
+            events.clone(),
             results.clone(),
+            stats.clone(),
+            errors.clone(),
             child_stdout,
         ));
+        // read as it's written, so that rg never blocks on a full pipe
+        let child_stderr = process.stderr.take().unwrap();
+        let stderr_thread = thread::spawn(Self::stderr_impl_factory(
+            name.clone(),
+            events.clone(),
+            errors.clone(),
+            child_stderr,
+        ));
 
         Ok(RgWorker {
             name,
-            pid,
             process,
+            events,
             thread: Some(thread),
+            stderr_thread: Some(stderr_thread),
             results,
+            stats,
+            errors,
         })
     }
 
-    pub fn pid(&self) -> u32 {
-        self.pid
-    }
-
     pub fn kill_and_wait(&mut self) -> Result<(), Box<dyn Error>> {
-        if self.process.try_wait().is_err() {
-            self.process.kill()?;
+        match self.process.try_wait()? {
+            Some(status) => self.exited(status),
+            // stopped before it was done, so its exit status says nothing
+            None => {
+                self.process.kill()?;
+                self.process.wait()?;
+            }
         }
-
-        self.thread
-            .take()
-            .map(|thread| {
-                thread
-                    .join()
-                    .map_err(|err| format!("{} error: {:?}", self.name, err).into())
-            })
-            .unwrap_or(Ok(()))
+        self.join()
     }
 
     pub fn finished(&mut self) -> bool {
-        self.thread
-            .as_ref()
-            .map(|thread| thread.is_finished())
-            .unwrap_or(true)
+        [&self.thread, &self.stderr_thread]
+            .iter()
+            .all(|thread| thread.as_ref().is_none_or(|thread| thread.is_finished()))
             && self
                 .process
                 .try_wait()
                 .map_or_else(|_| true, |opt| opt.is_some())
     }
 
-    pub fn results(&self) -> MutexGuard<Vec<MatchedFile>> {
+    pub fn results(&self) -> MutexGuard<'_, Vec<MatchedFile>> {
         self.results.lock()
     }
 
-    fn worker_impl_factory(
-        mut events_sender: Sender<AppEvent>,
+    /// rg's `summary` of the search, once it has finished.
+    pub fn stats(&self) -> Option<SearchStats> {
+        *self.stats.lock()
+    }
+
+    /// What rg has written to stderr so far, one line each, and its exit
+    /// status if it failed.
+    pub fn errors(&self) -> Vec<String> {
+        self.errors.lock().clone()
+    }
+
+    /// Blocks until `rg` has exited and all of its output has been read,
+    /// returning every matched file. Fails if rg failed without finding
+    /// anything, e.g. on an invalid pattern.
+    pub fn wait(&mut self) -> Result<Vec<MatchedFile>, Box<dyn Error>> {
+        self.join()?;
+        let status = self.process.wait()?;
+        self.exited(status);
+
+        let results = mem::take(&mut *self.results.lock());
+        if results.is_empty() && !Self::succeeded(status) {
+            Err(self.errors().join("\n"))?;
+        }
+        Ok(results)
+    }
+
+    // rg exits with 1 if nothing matched, and 2 on any error, even if it
+    // carried on past it
+    fn succeeded(status: ExitStatus) -> bool {
+        matches!(status.code(), Some(0) | Some(1))
+    }
+
+    fn exited(&mut self, status: ExitStatus) {
+        if !Self::succeeded(status) {
+            let error = format!("rg exited with {}", status);
+            self.events.error(format!("rg {}: {}", self.name, error));
+            self.errors.lock().push(error);
+        }
+    }
+
+    fn join(&mut self) -> Result<(), Box<dyn Error>> {
+        for thread in [self.thread.take(), self.stderr_thread.take()]
+            .into_iter()
+            .flatten()
+        {
+            thread
+                .join()
+                .map_err(|err| format!("{} error: {:?}", self.name, err))?;
+        }
+        Ok(())
+    }
+
+    fn worker_impl_factory<R: Read>(
+        notify: Box<dyn Fn() + Send>,
         name: String,
         mut events: EventLog,
         matches: Arc<Mutex<Vec<MatchedFile>>>,
-        mut child_stdout: ChildStdout,
+        stats: Arc<Mutex<Option<SearchStats>>>,
+        errors: Arc<Mutex<Vec<String>>>,
+        child_stdout: R,
     ) -> impl FnOnce() {
         move || {
-            let mut buf = vec![0u8; 4096];
-            let mut str_buf = String::new();
-            let mut finished = false;
+            // one JSON record per line; read as raw bytes, so that a
+            // character split across reads stays whole
+            let mut reader = BufReader::new(child_stdout);
+            let mut record = vec![];
             let mut in_progress_found = MatchedFileBuilder::default();
 
             events.info(format!("rg {}: waiting for stdout", name));
 
             loop {
-                let num_read = child_stdout.read(&mut buf).unwrap();
-                if num_read == 0 {
-                    events.info(format!("rg {}: end of file", name));
-                    finished = true;
+                record.clear();
+                match reader.read_until(b'\n', &mut record) {
+                    Ok(0) => {
+                        events.info(format!("rg {}: end of file", name));
+                        break;
+                    }
+                    Ok(_) => {}
+                    Err(err) => {
+                        events.error(format!("rg {}: reading stdout: {}", name, err));
+                        break;
+                    }
                 }
 
-                let as_str = std::str::from_utf8(&buf[0..num_read]).unwrap();
-
-                str_buf.push_str(as_str);
-
-                // find location of next newline
-                'no_nl: loop {
-                    let cmd_end = if finished {
-                        str_buf.len()
-                    } else {
-                        match str_buf.find('\n') {
-                            Some(pos) => pos + 1,
-                            None => break 'no_nl,
-                        }
-                    };
-
-                    let (command, rest) = str_buf.split_at(cmd_end);
-                    if !command.is_empty() {
-                        let command: Value = serde_json::from_str(command).unwrap();
+                let handled = serde_json::from_slice(&record)
+                    .map_err(|err| err.into())
+                    .and_then(|command: Value| {
                         Self::handle_command(
-                            &mut events_sender,
+                            &notify,
                             &name,
                             &mut in_progress_found,
                             &mut events,
                             &matches,
-                            command,
-                        );
-                    }
+                            &stats,
+                            &errors,
+                            &command,
+                        )
+                    });
+                if let Err(err) = handled {
+                    events.error(format!(
+                        "rg {}: skipping malformed record ({}): {}",
+                        name,
+                        err,
+                        String::from_utf8_lossy(&record).trim_end()
+                    ));
+                }
+            }
+        }
+    }
 
-                    str_buf = rest.to_owned();
-                    if finished || str_buf.is_empty() {
+    fn stderr_impl_factory<R: Read>(
+        name: String,
+        mut events: EventLog,
+        errors: Arc<Mutex<Vec<String>>>,
+        child_stderr: R,
+    ) -> impl FnOnce() {
+        move || {
+            for line in BufReader::new(child_stderr).split(b'\n') {
+                match line {
+                    Ok(line) => {
+                        let line = decode(&line).trim_end().to_owned();
+                        if !line.is_empty() {
+                            events.error(format!("rg {}: {}", name, line));
+                            errors.lock().push(line);
+                        }
+                    }
+                    Err(err) => {
+                        events.error(format!("rg {}: reading stderr: {}", name, err));
                         break;
                     }
                 }
-
-                if finished {
-                    break;
-                }
             }
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn handle_command(
-        events_sender: &mut Sender<AppEvent>,
+        notify: &dyn Fn(),
         name: &str,
         builder: &mut MatchedFileBuilder,
         events: &mut EventLog,
         matches: &Arc<Mutex<Vec<MatchedFile>>>,
-        command: Value,
-    ) {
+        stats: &Mutex<Option<SearchStats>>,
+        errors: &Mutex<Vec<String>>,
+        command: &Value,
+    ) -> Result<(), Box<dyn Error>> {
         // events.info(format!("rg command: {}", command));
 
-        if command["type"] == "begin" {
-            builder.file_path = command["data"]["path"]["text"].as_str().unwrap().to_owned();
-        }
+        match command["type"].as_str() {
+            Some("begin") => {
+                // the matches of a path that can't be renamed are skipped
+                builder.lines.clear();
+                builder.file_path = None;
+                let path = data_bytes(&command["data"]["path"])?;
+                let file_path = match String::from_utf8(path) {
+                    Ok(file_path) => file_path,
+                    Err(err) => {
+                        let file_path = String::from_utf8_lossy(err.as_bytes());
+                        events.error(format!(
+                            "rg {}: skipping {}, its path isn't valid UTF-8",
+                            name, file_path
+                        ));
+                        errors
+                            .lock()
+                            .push(format!("{}: path isn't valid UTF-8, skipped", file_path));
+                        return Ok(());
+                    }
+                };
+                // read once, to tell if the file changes before it's replaced
+                // in, and how its lines are encoded; if it's gone already, it
+                // will have changed by then
+                let contents = std::fs::read(&file_path).ok();
+                builder.content_hash = contents.as_deref().map(hash);
+                builder.encoding = contents.as_deref().map(Encoding::of);
+                builder.file_path = Some(file_path);
+            }
 
-        if command["type"] == "end" {
-            let found = builder.build();
-            events.info(format!("rg {}: match in `{:?}`", name, found.file_path()));
-            matches.lock().push(found);
-            events_sender.send(AppEvent::WorkerUpdate).unwrap();
-        }
+            Some("end") => {
+                if let Some(found) = builder.build() {
+                    events.info(format!("rg {}: match in `{:?}`", name, found.file_path()));
+                    matches.lock().push(found);
+                    notify();
+                }
+            }
 
-        if command["type"] == "context" {
-            Self::push_context(builder, &command, vec![]);
-        }
+            Some("context") => Self::push_context(builder, command, false)?,
+            Some("match") => Self::push_context(builder, command, true)?,
 
-        if command["type"] == "match" {
-            let subs = command["data"]["submatches"]
-                .as_array()
-                .unwrap()
-                .iter()
-                .map(|submatch| {
-                    let start = submatch["start"].as_u64().unwrap() as usize;
-                    let end = submatch["end"].as_u64().unwrap() as usize;
-                    start..end
-                })
-                .collect();
-            Self::push_context(builder, &command, subs);
+            Some("summary") => {
+                let summary = Self::summary(&command["data"])?;
+                events.info(format!("rg {}: {}", name, summary));
+                *stats.lock() = Some(summary);
+            }
+
+            Some(_) => {}
+            None => Err("no record type")?,
         }
+        Ok(())
     }
 
     fn push_context(
         builder: &mut MatchedFileBuilder,
         command: &Value,
-        submatches: Vec<Range<usize>>,
-    ) {
+        matched: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        if builder.file_path.is_none() {
+            return Ok(());
+        }
+
         // lines are 1-indexed from rg, sub 1 to make it zero indexed
-        let line_num = command["data"]["line_number"].as_u64().unwrap() as usize - 1;
-        let value = command["data"]["lines"]["text"]
-            .as_str()
-            .unwrap()
-            .to_owned();
+        let line_num = command["data"]["line_number"]
+            .as_u64()
+            .and_then(|line_num| line_num.checked_sub(1))
+            .ok_or("no line number")? as usize;
+        let line = data_bytes(&command["data"]["lines"])?;
+        let encoding = builder.encoding.unwrap_or_else(|| Encoding::of(&line));
 
-        builder.lines.push(Line::new(line_num, value, submatches));
+        let mut submatches = vec![];
+        if matched {
+            let records = command["data"]["submatches"]
+                .as_array()
+                .ok_or("no submatches")?;
+            for submatch in records {
+                let (start, end) = match (submatch["start"].as_u64(), submatch["end"].as_u64()) {
+                    (Some(start), Some(end)) if start <= end && end as usize <= line.len() => {
+                        (start as usize, end as usize)
+                    }
+                    _ => Err(format!("bad submatch {}", submatch))?,
+                };
+                // empty matches, e.g. of `^`, have nothing to replace
+                if start < end {
+                    submatches.push(
+                        encoding.decoded_offset(&line, start)..encoding.decoded_offset(&line, end),
+                    );
+                }
+            }
+        }
+
+        builder
+            .lines
+            .push(Line::new(line_num, encoding.decode(&line), submatches));
+        Ok(())
+    }
+
+    fn summary(data: &Value) -> Result<SearchStats, Box<dyn Error>> {
+        let field = |value: &Value| value.as_u64().ok_or("bad summary");
+        let elapsed = &data["elapsed_total"];
+        Ok(SearchStats {
+            files_searched: field(&data["stats"]["searches"])?,
+            bytes_searched: field(&data["stats"]["bytes_searched"])?,
+            matches: field(&data["stats"]["matches"])?,
+            elapsed: Duration::new(field(&elapsed["secs"])?, field(&elapsed["nanos"])? as u32),
+        })
+    }
+}
+
+// a path or line as rg encodes it: `{"text": ..}`, or base64 `{"bytes": ..}`
+// if it isn't valid UTF-8, e.g. in a Latin-1 source file
+fn data_bytes(data: &Value) -> Result<Vec<u8>, Box<dyn Error>> {
+    if let Some(text) = data["text"].as_str() {
+        return Ok(text.as_bytes().to_vec());
+    }
+    match data["bytes"].as_str() {
+        Some(bytes) => Ok(STANDARD.decode(bytes)?),
+        None => Err(format!("expected text or bytes, got {}", data).into()),
     }
 }
 
 #[derive(Default)]
 struct MatchedFileBuilder {
-    file_path: String,
+    // `None` while skipping a file
+    file_path: Option<String>,
     lines: Vec<Line>,
+    content_hash: Option<String>,
+    // of the whole file, which each line is decoded as; `None` if it
+    // couldn't be read
+    encoding: Option<Encoding>,
 }
 impl MatchedFileBuilder {
-    fn build(&mut self) -> MatchedFile {
-        MatchedFile::new(mem::take(&mut self.file_path), mem::take(&mut self.lines))
+    fn build(&mut self) -> Option<MatchedFile> {
+        let mut found = MatchedFile::new(self.file_path.take()?, mem::take(&mut self.lines));
+        found.set_content_hash(self.content_hash.take());
+        Some(found)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Arc, time::Duration};
+
+    use itertools::assert_equal;
+    use parking_lot::Mutex;
+
+    use crate::{event_log::EventLog, matched_file::MatchedFile, search_backend::SearchStats};
+
+    use super::RgWorker;
+
+    // `rg --json` output, as read in chunks of `len` bytes
+    struct Chunked<'a> {
+        bytes: &'a [u8],
+        len: usize,
+    }
+
+    impl<'a> std::io::Read for Chunked<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let len = self.len.min(buf.len()).min(self.bytes.len());
+            buf[..len].copy_from_slice(&self.bytes[..len]);
+            self.bytes = &self.bytes[len..];
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn test_decode_stream() {
+        let stdout = [
+            r#"{"type":"begin","data":{"path":{"text":"Café.java"}}}"#,
+            r#"{"type":"match","data":{"path":{"text":"Café.java"},"lines":{"text":"class Café { Bar b; }\n"},"line_number":1,"submatches":[{"match":{"text":"Bar"},"start":14,"end":17}]}}"#,
+            r#"{"type":"end","data":{"path":{"text":"Café.java"}}}"#,
+            r#"{"type":"begin","data":{"path":{"text":"Latin1.java"}}}"#,
+            // `// caf\xe9` in Latin-1, then `Bar b;`
+            r#"{"type":"context","data":{"path":{"text":"Latin1.java"},"lines":{"bytes":"Ly8gY2Fm6Qo="},"line_number":1,"submatches":[]}}"#,
+            r#"{"type":"match","data":{"path":{"text":"Latin1.java"},"lines":{"bytes":"QmFyIGI7IC8vIOkK"},"line_number":2,"submatches":[{"match":{"text":"Bar"},"start":0,"end":3}]}}"#,
+            r#"{"type":"match","data":{"path":{"text":"Latin1.java"},"lines":{"nope":1},"line_number":3,"submatches":[]}}"#,
+            r#"{"type":"#,
+            r#"{"type":"end","data":{"path":{"text":"Latin1.java"}}}"#,
+            r#"{"type":"begin","data":{"path":{"bytes":"/w=="}}}"#,
+            r#"{"type":"match","data":{"path":{"bytes":"/w=="},"lines":{"text":"Bar\n"},"line_number":1,"submatches":[{"match":{"text":"Bar"},"start":0,"end":3}]}}"#,
+            r#"{"type":"end","data":{"path":{"bytes":"/w=="}}}"#,
+            r#"{"data":{"elapsed_total":{"human":"0.001500s","nanos":1500000,"secs":0},"stats":{"bytes_printed":481,"bytes_searched":74,"elapsed":{"human":"0.000046s","nanos":46375,"secs":0},"matched_lines":3,"matches":3,"searches":3,"searches_with_match":3}},"type":"summary"}"#,
+        ]
+        .join("\n");
+
+        let events = EventLog::default();
+        let matches: Arc<Mutex<Vec<MatchedFile>>> = Default::default();
+        let stats: Arc<Mutex<Option<SearchStats>>> = Default::default();
+        let errors: Arc<Mutex<Vec<String>>> = Default::default();
+        RgWorker::worker_impl_factory(
+            Box::new(|| {}),
+            "test".to_owned(),
+            events.clone(),
+            matches.clone(),
+            stats.clone(),
+            errors.clone(),
+            Chunked {
+                bytes: stdout.as_bytes(),
+                len: 7,
+            },
+        )();
+
+        let matches = matches.lock();
+        assert_equal(
+            ["Café.java", "Latin1.java"],
+            matches.iter().map(|found| found.file_path()),
+        );
+        assert_equal(
+            [vec![
+                (false, "class Café { "),
+                (true, "Bar"),
+                (false, " b; }\n"),
+            ]],
+            matches[0]
+                .lines()
+                .map(|line| line.iter().collect::<Vec<_>>()),
+        );
+        assert_equal(
+            [
+                vec![(false, "// caf\u{e9}\n")],
+                vec![(true, "Bar"), (false, " b; // \u{e9}\n")],
+            ],
+            matches[1]
+                .lines()
+                .map(|line| line.iter().collect::<Vec<_>>()),
+        );
+
+        // the line without text, and the cut off record
+        assert_eq!(
+            2,
+            events
+                .list()
+                .iter()
+                .filter(|line| line.value.contains("malformed record"))
+                .count()
+        );
+        // a path that can't be renamed, under the name it has
+        assert_equal(
+            ["\u{fffd}: path isn't valid UTF-8, skipped"],
+            errors.lock().iter().map(|error| error.as_str()),
+        );
+
+        assert_eq!(
+            Some(SearchStats {
+                files_searched: 3,
+                bytes_searched: 74,
+                matches: 3,
+                elapsed: Duration::from_micros(1500),
+            }),
+            *stats.lock()
+        );
+    }
+
+    #[test]
+    fn test_file_encoding() {
+        // a Latin-1 file, with a line that happens to be valid UTF-8
+        let path = std::env::temp_dir().join(format!("fqn-renamer-rg-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        std::fs::write(path, b"// caf\xc3\xa9\nBar b; // \xe9\n").unwrap();
+        let stdout = [
+            format!(r#"{{"type":"begin","data":{{"path":{{"text":"{}"}}}}}}"#, path),
+            format!(
+                r#"{{"type":"context","data":{{"path":{{"text":"{}"}},"lines":{{"bytes":"Ly8gY2Fmw6kK"}},"line_number":1,"submatches":[]}}}}"#,
+                path
+            ),
+            format!(
+                r#"{{"type":"match","data":{{"path":{{"text":"{}"}},"lines":{{"bytes":"QmFyIGI7IC8vIOkK"}},"line_number":2,"submatches":[{{"match":{{"text":"Bar"}},"start":0,"end":3}}]}}}}"#,
+                path
+            ),
+            format!(r#"{{"type":"end","data":{{"path":{{"text":"{}"}}}}}}"#, path),
+        ]
+        .join("\n");
+
+        let matches: Arc<Mutex<Vec<MatchedFile>>> = Default::default();
+        RgWorker::worker_impl_factory(
+            Box::new(|| {}),
+            "test".to_owned(),
+            Default::default(),
+            matches.clone(),
+            Default::default(),
+            Default::default(),
+            stdout.as_bytes(),
+        )();
+
+        // as the whole file is read when it's renamed
+        let matches = matches.lock();
+        assert_equal(
+            ["// caf\u{c3}\u{a9}\n", "Bar b; // \u{e9}\n"],
+            matches[0].lines().map(|line| line.value()),
+        );
+        assert!(!matches[0].is_stale());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_stderr() {
+        let stderr = "rg: regex parse error:\n    (?:Bar()\n\nerror: unclosed group\n";
+
+        let errors: Arc<Mutex<Vec<String>>> = Default::default();
+        RgWorker::stderr_impl_factory(
+            "test".to_owned(),
+            Default::default(),
+            errors.clone(),
+            stderr.as_bytes(),
+        )();
+
+        assert_equal(
+            [
+                "rg: regex parse error:",
+                "    (?:Bar()",
+                "error: unclosed group",
+            ],
+            errors.lock().iter().map(|line| line.as_str()),
+        );
     }
 }
@@ -0,0 +1,380 @@
+use std::error::Error;
+
+use itertools::Itertools;
+use regex::Regex;
+
+use crate::{
+    event_log::EventLog,
+    fqcn::Fqcn,
+    fqcn_processor::{process_matched_file_fqcn, process_matched_file_package},
+    mapping::Mapping,
+    matched_file::{Line, MatchedFile},
+    package::Package,
+    search_backend::{SearchBackend, SearchStats, SearchWorker},
+};
+
+/// What to search a directory for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Search {
+    /// Files that reference a FQCN, either fully qualified, through an
+    /// import, or from within the same package
+    Fqcn(Fqcn),
+    /// Files that declare, import or fully qualify anything in a package or
+    /// its subpackages
+    Package(Package),
+    /// Every occurrence of a bare identifier
+    Ident(String),
+    /// Anything that any of the searches would find, to search for all of
+    /// them in one pass over the tree; see [`Search::split`]
+    Any(Vec<Search>),
+}
+
+impl Search {
+    /// Searches for a FQCN or package if `find` parses as one, or a bare
+    /// identifier if not.
+    pub fn new(find: &str) -> Search {
+        if let Some(fqcn) = Fqcn::new(find) {
+            Search::Fqcn(fqcn)
+        } else if let Some(package) = Package::new(find) {
+            Search::Package(package)
+        } else {
+            Search::Ident(find.to_owned())
+        }
+    }
+
+    /// Searches for the `find` side of a batch rename pair. A binary name
+    /// that isn't a class in a package finds nothing, rather than every bare
+    /// `a`; see [`Mapping::unsupported`].
+    pub fn for_mapping(mapping: &Mapping) -> Search {
+        match mapping.find_fqcn() {
+            Some(fqcn) if mapping.binary => Search::Fqcn(fqcn),
+            None if mapping.binary => Search::Any(vec![]),
+            _ => Search::new(&mapping.find),
+        }
+    }
+
+    /// Searches for every pair of a batch rename in one pass; see
+    /// [`Search::split`] for what each of them found.
+    pub fn for_batch(mappings: &[Mapping]) -> Search {
+        Search::Any(mappings.iter().map(Search::for_mapping).collect())
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Search::Fqcn(_) => "fqcn",
+            Search::Package(_) => "package",
+            Search::Ident(_) => "ident",
+            Search::Any(_) => "any",
+        }
+    }
+
+    /// The regex that finds candidate lines, with false positives that
+    /// [`Search::process`] filters out.
+    pub fn pattern(&self) -> String {
+        match self {
+            Search::Fqcn(fqcn) => {
+                // find the thing that defines the package, references the
+                // identifier (filter out the false positives later),
+                // or imports the identifier (use that for filtering)
+                let mut patterns = vec![
+                    // `package foo.Bar`
+                    format!(r"^package {};?$", regex::escape(fqcn.package())),
+                    // `Bar`
+                    format!(r"\b{}\b", regex::escape(fqcn.ident())),
+                    // `foo.Bar`
+                    format!(r"\b{}\b", regex::escape(fqcn.value())),
+                    // `import foo.Bar`, `import foo.Bar.Inner`,
+                    // `import static foo.Bar.CONSTANT`, `import static foo.Bar.*`,
+                    // `import foo.Bar as Baz`
+                    format!(
+                        r"^import (static )?{}(\.[\w*]+)*( as \w+)?;?$",
+                        regex::escape(fqcn.value())
+                    ),
+                    // `import foo.*`
+                    format!(r"^import {}\.\*;?$", regex::escape(fqcn.package())),
+                ];
+
+                if fqcn.is_nested() {
+                    // `Outer.Inner`
+                    patterns.push(format!(r"\b{}\b", regex::escape(fqcn.class_name())));
+                    // `foo.Outer$Inner`
+                    patterns.push(format!(r"\b{}\b", regex::escape(&fqcn.binary_name())));
+                    // `import foo.Outer`
+                    patterns.push(format!(
+                        r"^import {};?$",
+                        regex::escape(fqcn.top_level().value())
+                    ));
+                    // `class Outer`, where a bare `Inner` refers to it
+                    patterns.push(format!(
+                        r"\b(class|interface|enum|object|record)\s+{}\b",
+                        regex::escape(fqcn.top_level().ident())
+                    ));
+                }
+
+                patterns
+                    .iter()
+                    .map(|pattern| format!("({})", pattern))
+                    .join("|")
+            }
+            // `package foo.bar`, `import foo.bar.Baz`, `foo.bar.sub.Baz`, but
+            // not `com.foo.bar` (the leading character is trimmed off later)
+            Search::Package(package) => {
+                format!(r"(^|[^\w.]){}\b", regex::escape(package.value()))
+            }
+            Search::Ident(ident) => format!("\\b{}\\b", ident),
+            // every line that any of the searches matches contains one of
+            // their names, so this finds a few more lines than they would,
+            // but stays a single (fast) alternation of literals however
+            // many searches there are
+            Search::Any(searches) => {
+                let mut patterns = searches
+                    .iter()
+                    .filter(|search| matches!(search, Search::Ident(_)))
+                    .map(|search| format!("({})", search.pattern()))
+                    .unique()
+                    .collect::<Vec<_>>();
+                let names = searches
+                    .iter()
+                    .flat_map(|search| search.names())
+                    .unique()
+                    .map(|name| regex::escape(&name))
+                    .join("|");
+                if !names.is_empty() {
+                    patterns.push(format!(r"\b({})\b", names));
+                }
+                if patterns.is_empty() {
+                    // nothing to search for, and an empty pattern matches
+                    // every line
+                    return r"[^\s\S]".to_owned();
+                }
+                patterns.join("|")
+            }
+        }
+    }
+
+    // the names that every line `pattern` matches contains at least one of,
+    // as a whole word
+    fn names(&self) -> Vec<String> {
+        match self {
+            Search::Fqcn(fqcn) => {
+                let top_level = fqcn.top_level();
+                vec![
+                    fqcn.ident().to_owned(),
+                    fqcn.value().to_owned(),
+                    fqcn.class_name().to_owned(),
+                    fqcn.binary_name(),
+                    // `package foo`, `import foo.*`
+                    fqcn.package().to_owned(),
+                    // `import foo.Outer`, `class Outer`
+                    top_level.value().to_owned(),
+                    top_level.ident().to_owned(),
+                ]
+            }
+            Search::Package(package) => vec![package.value().to_owned()],
+            Search::Ident(_) => vec![],
+            Search::Any(searches) => searches.iter().flat_map(|search| search.names()).collect(),
+        }
+    }
+
+    /// How many lines around each match to search for, e.g. a file's
+    /// `package` line.
+    pub fn context_lines(&self) -> usize {
+        match self {
+            Search::Fqcn(_) | Search::Package(_) => 2,
+            Search::Ident(_) => 1,
+            Search::Any(searches) => searches
+                .iter()
+                .map(|search| search.context_lines())
+                .max()
+                .unwrap_or(0),
+        }
+    }
+
+    /// The arguments to run `rg` with to search `base_dir`.
+    pub fn rg_args(&self, base_dir: &str) -> Vec<String> {
+        vec![
+            "--json".to_owned(),
+            // so `$` matches in front of `\r\n` too
+            "--crlf".to_owned(),
+            format!("-C{}", self.context_lines()),
+            self.pattern(),
+            base_dir.to_owned(),
+        ]
+    }
+
+    /// Filters out false positives from the raw `rg` results.
+    pub fn process(&self, results: Vec<MatchedFile>) -> Vec<MatchedFile> {
+        match self {
+            Search::Fqcn(fqcn) => process_matched_file_fqcn(fqcn, results),
+            Search::Package(package) => process_matched_file_package(package, results),
+            Search::Ident(_) | Search::Any(_) => results,
+        }
+    }
+
+    /// What each search of [`Search::Any`] found among its raw results, in
+    /// order: the same lines, with the same submatches, that searching for it
+    /// on its own would have found, with its false positives filtered out.
+    pub fn split(&self, results: Vec<MatchedFile>) -> Vec<Vec<MatchedFile>> {
+        let searches = match self {
+            Search::Any(searches) => searches,
+            _ => return vec![self.process(results)],
+        };
+
+        searches
+            .iter()
+            .map(|search| {
+                // built from escaped names, or already searched for by rg
+                let regex = match Regex::new(&search.pattern()) {
+                    Ok(regex) => regex,
+                    Err(_) => return vec![],
+                };
+                let found = results
+                    .iter()
+                    .filter_map(|matched_file| {
+                        own_lines(matched_file, &regex, search.context_lines())
+                    })
+                    .collect();
+                search.process(found)
+            })
+            .collect()
+    }
+
+    /// Starts searching in the background with `backend`, calling `notify`
+    /// whenever the worker has new results.
+    pub fn spawn<N>(
+        &self,
+        backend: &dyn SearchBackend,
+        base_dir: &str,
+        events: EventLog,
+        notify: N,
+    ) -> Result<Box<dyn SearchWorker>, Box<dyn Error>>
+    where
+        N: Fn() + Send + Sync + 'static,
+    {
+        backend.spawn(self, base_dir, events, Box::new(notify))
+    }
+
+    /// Searches `base_dir` with `backend`, blocking until it's done.
+    pub fn run(
+        &self,
+        backend: &dyn SearchBackend,
+        base_dir: &str,
+        events: &mut EventLog,
+    ) -> Result<SearchOutput, Box<dyn Error>> {
+        let mut worker = self.spawn(backend, base_dir, events.clone(), || {})?;
+        events.info(format!("start {} ({})", backend.name(), self.name()));
+        let results = worker.wait()?;
+        Ok(SearchOutput {
+            matches: self.process(results),
+            stats: worker.stats(),
+            errors: worker.errors(),
+        })
+    }
+}
+
+// the lines of `matched_file` that `regex` matches, with their own
+// submatches, and the lines within `context` lines of them; `None` if it
+// matches none
+fn own_lines(matched_file: &MatchedFile, regex: &Regex, context: usize) -> Option<MatchedFile> {
+    let lines = matched_file
+        .lines()
+        .map(|line| {
+            let value = line.value();
+            let text = value.trim_end_matches(['\r', '\n']);
+            // empty matches, e.g. of `^`, have nothing to replace
+            let submatches = regex
+                .find_iter(text)
+                .map(|found| found.range())
+                .filter(|range| !range.is_empty())
+                .collect::<Vec<_>>();
+            Line::new(line.num(), value, submatches)
+        })
+        .collect::<Vec<_>>();
+
+    let matched = lines
+        .iter()
+        .filter(|line| line.num_submatches() > 0)
+        .map(|line| line.num())
+        .collect::<Vec<_>>();
+    if matched.is_empty() {
+        return None;
+    }
+    let lines = lines
+        .into_iter()
+        .filter(|line| {
+            matched
+                .iter()
+                .any(|&num| num.abs_diff(line.num()) <= context)
+        })
+        .collect::<Vec<_>>();
+
+    let mut found = MatchedFile::new(matched_file.file_path(), lines);
+    found.set_content_hash(matched_file.content_hash().map(|hash| hash.to_owned()));
+    Some(found)
+}
+
+/// What [`Search::run`] found.
+pub struct SearchOutput {
+    pub matches: Vec<MatchedFile>,
+    pub stats: Option<SearchStats>,
+    /// The errors the search carried on past, e.g. files it couldn't read
+    pub errors: Vec<String>,
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{mapping::Mapping, matched_file::MatchedFile, search_backend::GrepBackend};
+
+    use super::Search;
+
+    #[test]
+    fn test_split() {
+        let dir = std::env::temp_dir().join(format!("fqn-renamer-split-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("com/foo")).unwrap();
+        std::fs::write(
+            dir.join("com/foo/Outer.java"),
+            "package com.foo;\n\npublic class Outer {\n  static class Inner {}\n  Bar bar;\n}\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("User.java"),
+            "package net.baz;\n\nimport com.foo.*;\nimport javax.servlet.Filter;\n\nclass User {\n  Outer.Inner inner;\n  Bar bar;\n  Filter filter;\n  int a = 1;\n}\n",
+        )
+        .unwrap();
+        let base_dir = dir.to_str().unwrap();
+
+        let mappings = [
+            Mapping::new("com.foo.Bar", "com.foo.Baz"),
+            Mapping::new("com.foo.Outer.Inner", "com.foo.Outer.Renamed"),
+            Mapping::new("javax.servlet", "jakarta.servlet"),
+            Mapping::new("Filter", "Sieve"),
+            Mapping::new("com.foo.Unused", "com.foo.Other"),
+            // reversed from R8's `-repackageclasses ''`, not a bare `a`
+            Mapping::binary("a", "com.foo.Repackaged"),
+        ];
+        let searches = mappings.iter().map(Search::for_mapping).collect::<Vec<_>>();
+        let sorted = |mut found: Vec<MatchedFile>| {
+            found.sort_by(|a, b| a.file_path().cmp(b.file_path()));
+            found
+        };
+
+        let search = Search::Any(searches.clone());
+        let output = search
+            .run(&GrepBackend, base_dir, &mut Default::default())
+            .unwrap();
+        let split = search.split(output.matches);
+
+        assert_eq!(
+            vec![2, 2, 1, 1, 0, 0],
+            split.iter().map(|found| found.len()).collect::<Vec<_>>()
+        );
+        for (search, found) in searches.iter().zip(split) {
+            let alone = search
+                .run(&GrepBackend, base_dir, &mut Default::default())
+                .unwrap();
+            assert_eq!(sorted(alone.matches), sorted(found), "{:?}", search);
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
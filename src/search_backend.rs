@@ -0,0 +1,183 @@
+use std::{error::Error, fmt, ops::AddAssign, time::Duration};
+
+use parking_lot::MutexGuard;
+
+use crate::{
+    event_log::EventLog, grep_worker::GrepWorker, matched_file::MatchedFile, rg_worker::RgWorker,
+    search::Search,
+};
+
+/// Runs a [`Search`] over a directory in the background.
+pub trait SearchBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Starts searching `base_dir`, calling `notify` each time a matched file
+    /// has been added to [`SearchWorker::results`].
+    fn spawn(
+        &self,
+        search: &Search,
+        base_dir: &str,
+        events: EventLog,
+        notify: Box<dyn Fn() + Send + Sync>,
+    ) -> Result<Box<dyn SearchWorker>, Box<dyn Error>>;
+}
+
+/// A search started by a [`SearchBackend`].
+pub trait SearchWorker: Send {
+    /// The matched files found so far, not yet taken.
+    fn results(&self) -> MutexGuard<'_, Vec<MatchedFile>>;
+
+    fn finished(&mut self) -> bool;
+
+    fn kill_and_wait(&mut self) -> Result<(), Box<dyn Error>>;
+
+    /// Blocks until the search is done, returning every matched file.
+    fn wait(&mut self) -> Result<Vec<MatchedFile>, Box<dyn Error>>;
+
+    /// How much was searched, once the search is done; `None` if it was
+    /// stopped.
+    fn stats(&self) -> Option<SearchStats>;
+
+    /// The errors the search carried on past so far, e.g. files it couldn't
+    /// read, oldest first.
+    fn errors(&self) -> Vec<String>;
+}
+
+/// How much a search went through, as `rg` reports in its `summary`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SearchStats {
+    pub files_searched: u64,
+    pub bytes_searched: u64,
+    /// Every match of the pattern, before false positives are filtered out
+    pub matches: u64,
+    pub elapsed: Duration,
+}
+
+impl fmt::Display for SearchStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "searched {} files ({} bytes) in {:.3}s, {} matches",
+            self.files_searched,
+            self.bytes_searched,
+            self.elapsed.as_secs_f64(),
+            self.matches
+        )
+    }
+}
+
+impl AddAssign for SearchStats {
+    fn add_assign(&mut self, other: SearchStats) {
+        self.files_searched += other.files_searched;
+        self.bytes_searched += other.bytes_searched;
+        self.matches += other.matches;
+        self.elapsed += other.elapsed;
+    }
+}
+
+/// Runs the `rg` binary, which has to be installed, and reads its `--json`
+/// output.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RgBackend;
+
+/// Searches in-process with ripgrep's crates, so needs nothing installed.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct GrepBackend;
+
+impl SearchBackend for RgBackend {
+    fn name(&self) -> &'static str {
+        "rg"
+    }
+
+    fn spawn(
+        &self,
+        search: &Search,
+        base_dir: &str,
+        events: EventLog,
+        notify: Box<dyn Fn() + Send + Sync>,
+    ) -> Result<Box<dyn SearchWorker>, Box<dyn Error>> {
+        let worker = RgWorker::new(
+            notify,
+            format!("{}_worker", search.name()),
+            events,
+            &search.rg_args(base_dir),
+        )?;
+        Ok(Box::new(worker))
+    }
+}
+
+impl SearchBackend for GrepBackend {
+    fn name(&self) -> &'static str {
+        "builtin"
+    }
+
+    fn spawn(
+        &self,
+        search: &Search,
+        base_dir: &str,
+        events: EventLog,
+        notify: Box<dyn Fn() + Send + Sync>,
+    ) -> Result<Box<dyn SearchWorker>, Box<dyn Error>> {
+        let worker = GrepWorker::new(
+            notify,
+            format!("{}_worker", search.name()),
+            events,
+            &search.pattern(),
+            search.context_lines(),
+            base_dir,
+        )?;
+        Ok(Box::new(worker))
+    }
+}
+
+impl SearchWorker for RgWorker {
+    fn results(&self) -> MutexGuard<'_, Vec<MatchedFile>> {
+        RgWorker::results(self)
+    }
+
+    fn finished(&mut self) -> bool {
+        RgWorker::finished(self)
+    }
+
+    fn kill_and_wait(&mut self) -> Result<(), Box<dyn Error>> {
+        RgWorker::kill_and_wait(self)
+    }
+
+    fn wait(&mut self) -> Result<Vec<MatchedFile>, Box<dyn Error>> {
+        RgWorker::wait(self)
+    }
+
+    fn stats(&self) -> Option<SearchStats> {
+        RgWorker::stats(self)
+    }
+
+    fn errors(&self) -> Vec<String> {
+        RgWorker::errors(self)
+    }
+}
+
+impl SearchWorker for GrepWorker {
+    fn results(&self) -> MutexGuard<'_, Vec<MatchedFile>> {
+        GrepWorker::results(self)
+    }
+
+    fn finished(&mut self) -> bool {
+        GrepWorker::finished(self)
+    }
+
+    fn kill_and_wait(&mut self) -> Result<(), Box<dyn Error>> {
+        GrepWorker::kill_and_wait(self)
+    }
+
+    fn wait(&mut self) -> Result<Vec<MatchedFile>, Box<dyn Error>> {
+        GrepWorker::wait(self)
+    }
+
+    fn stats(&self) -> Option<SearchStats> {
+        GrepWorker::stats(self)
+    }
+
+    fn errors(&self) -> Vec<String> {
+        GrepWorker::errors(self)
+    }
+}
@@ -0,0 +1,122 @@
+use std::borrow::Cow;
+
+/// The UTF-8 byte order mark. `rg` leaves it out of the lines it reports, so
+/// it's stripped before comparing or editing lines, and put back after.
+pub const BOM: char = '\u{feff}';
+
+/// `contents` without the byte order mark at its start, if it has one.
+pub fn strip_bom(contents: &str) -> &str {
+    contents.strip_prefix(BOM).unwrap_or(contents)
+}
+
+/// The line ending of the first line of `contents`, for new lines added to
+/// it: `\r\n` for CRLF files, `\n` otherwise.
+pub fn line_ending(contents: &str) -> &'static str {
+    match contents.split_once('\n') {
+        Some((line, _)) if line.ends_with('\r') => "\r\n",
+        _ => "\n",
+    }
+}
+
+/// How a file's bytes are [`decode`]d, and encoded again when it's written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    /// ISO 8859-1, for files that aren't valid UTF-8
+    Latin1,
+}
+
+impl Encoding {
+    pub fn of(bytes: &[u8]) -> Encoding {
+        match std::str::from_utf8(bytes) {
+            Ok(_) => Encoding::Utf8,
+            Err(_) => Encoding::Latin1,
+        }
+    }
+
+    /// `bytes`, a line or more of a file in this encoding, as text. Lines are
+    /// decoded as their whole file is, as a Latin-1 file can have lines that
+    /// happen to be valid UTF-8.
+    pub fn decode(self, bytes: &[u8]) -> Cow<'_, str> {
+        match self {
+            Encoding::Utf8 => String::from_utf8_lossy(bytes),
+            Encoding::Latin1 => Cow::Owned(bytes.iter().map(|&byte| char::from(byte)).collect()),
+        }
+    }
+
+    /// Where byte `offset` of `bytes` ends up in their [`Encoding::decode`]d
+    /// text.
+    pub fn decoded_offset(self, bytes: &[u8], offset: usize) -> usize {
+        match self {
+            Encoding::Utf8 => offset,
+            // Latin-1 bytes above ASCII take two bytes in UTF-8
+            Encoding::Latin1 => {
+                offset
+                    + bytes[..offset.min(bytes.len())]
+                        .iter()
+                        .filter(|&&byte| byte >= 0x80)
+                        .count()
+            }
+        }
+    }
+
+    /// `text` as bytes in this encoding, or `None` if it has characters that
+    /// Latin-1 can't hold.
+    pub fn encode(self, text: &str) -> Option<Cow<'_, [u8]>> {
+        match self {
+            Encoding::Utf8 => Some(Cow::Borrowed(text.as_bytes())),
+            Encoding::Latin1 => text
+                .chars()
+                .map(|ch| u8::try_from(ch).ok())
+                .collect::<Option<Vec<_>>>()
+                .map(Cow::Owned),
+        }
+    }
+}
+
+/// `bytes` as text: UTF-8 if they're valid UTF-8, Latin-1 (which every byte
+/// is valid in) if not.
+pub fn decode(bytes: &[u8]) -> Cow<'_, str> {
+    Encoding::of(bytes).decode(bytes)
+}
+
+/// The [`decode`]d contents of the file at `path`, and their encoding.
+pub fn read_text(path: &str) -> std::io::Result<(String, Encoding)> {
+    let bytes = std::fs::read(path)?;
+    let encoding = Encoding::of(&bytes);
+    Ok((decode(&bytes).into_owned(), encoding))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode, line_ending, strip_bom, Encoding};
+
+    #[test]
+    fn test_text() {
+        assert_eq!("package foo;\n", strip_bom("\u{feff}package foo;\n"));
+        assert_eq!("package foo;\n", strip_bom("package foo;\n"));
+
+        assert_eq!("\r\n", line_ending("package foo;\r\n\r\nclass Bar {}\r\n"));
+        assert_eq!("\n", line_ending("package foo;\n"));
+        assert_eq!("\n", line_ending("class Bar {}"));
+
+        assert_eq!("caf\u{e9} Bar", decode(b"caf\xe9 Bar"));
+        assert_eq!("caf\u{e9} Bar", decode("café Bar".as_bytes()));
+        assert_eq!(6, Encoding::Latin1.decoded_offset(b"caf\xe9 Bar", 5));
+        assert_eq!(6, Encoding::Utf8.decoded_offset("café Bar".as_bytes(), 6));
+        // valid UTF-8, in a Latin-1 file
+        assert_eq!(
+            "caf\u{c3}\u{a9}",
+            Encoding::Latin1.decode("café".as_bytes())
+        );
+        assert_eq!(7, Encoding::Latin1.decoded_offset("café".as_bytes(), 5));
+
+        assert_eq!(Encoding::Latin1, Encoding::of(b"caf\xe9 Bar"));
+        assert_eq!(Encoding::Utf8, Encoding::of("café Bar".as_bytes()));
+        assert_eq!(
+            Some(&b"caf\xe9 Quux"[..]),
+            Encoding::Latin1.encode("caf\u{e9} Quux").as_deref()
+        );
+        assert_eq!(None, Encoding::Latin1.encode("caf\u{e9} \u{20ac}"));
+    }
+}
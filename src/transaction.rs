@@ -0,0 +1,385 @@
+use std::{
+    error::Error,
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{backup::BackupSet, event_log::EventLog, paths::absolute, rename::FileMove};
+
+/// Rewrites and moves a set of files all at once, or not at all.
+///
+/// New contents are first staged next to each file, and only renamed into
+/// place once every one of them has been written. If anything fails after
+/// that, [`Transaction::rollback`] restores each file already touched from
+/// its backup, moves files back to where they were, and removes the backups
+/// and any directories created for the moved files.
+pub struct Transaction {
+    // where the backups go
+    base_dir: String,
+    // files with staged contents, not yet renamed into place
+    staged: Vec<String>,
+    // copies of the files from before they were replaced
+    backups: Option<BackupSet>,
+    // files replaced by their staged contents
+    replaced: Vec<String>,
+    // moves out of the way, and moves to their target
+    moving: Vec<FileMove>,
+    moved: Vec<FileMove>,
+    // directories created for moved files, parents first
+    created_dirs: Vec<PathBuf>,
+}
+
+impl Transaction {
+    /// A transaction that keeps its backups in `base_dir`, the directory
+    /// searched for the files it changes.
+    pub fn new(base_dir: &str) -> Transaction {
+        Transaction {
+            base_dir: base_dir.to_owned(),
+            staged: vec![],
+            backups: None,
+            replaced: vec![],
+            moving: vec![],
+            moved: vec![],
+            created_dirs: vec![],
+        }
+    }
+
+    /// Writes the new contents of `file_path` next to it, without touching
+    /// the file itself. The staged file gets the same permissions and, where
+    /// allowed, the same owner as the file it replaces.
+    pub fn stage(&mut self, file_path: &str, contents: &[u8]) -> Result<(), Box<dyn Error>> {
+        let staged_path = staged_path(file_path);
+        if std::fs::metadata(&staged_path).is_ok() {
+            Err(format!("{} already exists, aborting", staged_path))?;
+        }
+        let metadata =
+            std::fs::metadata(file_path).map_err(|err| format!("{}: {}", file_path, err))?;
+
+        self.staged.push(file_path.to_owned());
+        let file = File::create(&staged_path)?;
+        file.set_permissions(metadata.permissions())?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::{fchown, MetadataExt};
+            // only root can give a file away, anyone else keeps it as theirs
+            let _ = fchown(&file, Some(metadata.uid()), Some(metadata.gid()));
+        }
+        let mut writer = BufWriter::new(&file);
+        writer.write_all(contents)?;
+        writer.flush()?;
+        drop(writer);
+        file.sync_all()?;
+
+        // make sure it's all there before the original is given up on
+        let len = file.metadata()?.len();
+        if len != contents.len() as u64 {
+            Err(format!(
+                "{}: staged {} bytes, expected {}",
+                staged_path,
+                len,
+                contents.len()
+            ))?;
+        }
+        Ok(())
+    }
+
+    /// Copies each staged file into a new [`BackupSet`], then renames the
+    /// staged contents into place. Nothing staged makes no backup set.
+    pub fn commit(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.staged.is_empty() {
+            return Ok(());
+        }
+        let backups = self.backups.insert(BackupSet::create(&self.base_dir)?);
+        for file_path in self.staged.iter() {
+            backups.back_up(file_path)?;
+        }
+
+        while let Some(file_path) = self.staged.pop() {
+            self.replaced.push(file_path.clone());
+            std::fs::rename(staged_path(&file_path), &file_path)?;
+        }
+        Ok(())
+    }
+
+    /// Moves each file to its target. Files are first moved out of the way,
+    /// and only then to where they belong, so that swapped or chained moves
+    /// (`A -> B`, `B -> C`) don't run into each other.
+    pub fn move_files(
+        &mut self,
+        moves: &[FileMove],
+        events: &mut EventLog,
+    ) -> Result<(), Box<dyn Error>> {
+        for file_move in moves.iter() {
+            let vacated = moves.iter().any(|other| other.from == file_move.to);
+            if !vacated && std::fs::metadata(&file_move.to).is_ok() {
+                Err(format!("{} already exists, aborting", file_move.to))?;
+            }
+        }
+
+        for file_move in moves.iter() {
+            std::fs::rename(&file_move.from, moving_path(&file_move.from))?;
+            self.moving.push(file_move.clone());
+        }
+
+        for file_move in moves.iter() {
+            if let Some(parent) = Path::new(&file_move.to).parent() {
+                self.create_dirs(parent)?;
+            }
+            std::fs::rename(moving_path(&file_move.from), &file_move.to)?;
+            self.moving.retain(|moving| moving != file_move);
+            self.moved.push(file_move.clone());
+
+            events.info(format!(
+                "rename: moved {} to {}",
+                file_move.from, file_move.to
+            ));
+        }
+
+        Ok(())
+    }
+
+    // creates `dir` and any missing parents, remembering which were created
+    fn create_dirs(&mut self, dir: &Path) -> Result<(), Box<dyn Error>> {
+        let missing = dir
+            .ancestors()
+            .take_while(|ancestor| {
+                !ancestor.as_os_str().is_empty() && std::fs::metadata(ancestor).is_err()
+            })
+            .map(Path::to_path_buf)
+            .collect::<Vec<_>>();
+        std::fs::create_dir_all(dir)?;
+        self.created_dirs.extend(missing.into_iter().rev());
+        Ok(())
+    }
+
+    /// Records what the transaction did in the manifest of its backups, so
+    /// that it can be undone later.
+    pub fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        match &mut self.backups {
+            Some(backups) => backups.finish(&self.moved, &self.created_dirs),
+            None => Ok(()),
+        }
+    }
+
+    /// Removes the backups of a committed transaction, for when it's not to
+    /// be undone later, e.g. when it's an undo itself.
+    pub fn discard_backups(&mut self) -> Result<(), Box<dyn Error>> {
+        match self.backups.take() {
+            Some(backups) => backups.remove(),
+            None => Ok(()),
+        }
+    }
+
+    /// Undoes everything done so far: moves files back, removes the
+    /// directories created for them, restores replaced files from their
+    /// backups, and removes staged files and backups.
+    /// Carries on past errors, so as much as possible is restored, and
+    /// returns the first one.
+    pub fn rollback(&mut self, events: &mut EventLog) -> Result<(), Box<dyn Error>> {
+        let mut errors = vec![];
+
+        while let Some(file_move) = self.moved.pop() {
+            if let Err(err) = std::fs::rename(&file_move.to, &file_move.from) {
+                errors.push(format!("moving {} back: {}", file_move.to, err));
+            }
+        }
+        while let Some(file_move) = self.moving.pop() {
+            if let Err(err) = std::fs::rename(moving_path(&file_move.from), &file_move.from) {
+                errors.push(format!("moving {} back: {}", file_move.from, err));
+            }
+        }
+        // children first; anything left in them is kept, so they're kept too
+        while let Some(dir) = self.created_dirs.pop() {
+            let _ = std::fs::remove_dir(dir);
+        }
+
+        while let Some(file_path) = self.replaced.pop() {
+            let absolute_path = absolute(&file_path);
+            let backup = self
+                .backups
+                .as_ref()
+                .and_then(|backups| backups.journal())
+                .and_then(|journal| {
+                    journal
+                        .files
+                        .iter()
+                        .find(|file| Path::new(&file.path) == absolute_path)
+                });
+            let restored = match backup {
+                Some(backup) => std::fs::copy(&backup.backup, &file_path).map(|_| ()),
+                None => Err(std::io::ErrorKind::NotFound.into()),
+            };
+            if let Err(err) = restored {
+                errors.push(format!("restoring {}: {}", file_path, err));
+            }
+            let _ = std::fs::remove_file(staged_path(&file_path));
+        }
+        while let Some(file_path) = self.staged.pop() {
+            let _ = std::fs::remove_file(staged_path(&file_path));
+        }
+
+        // only drop the backups once every file is back to how it was
+        if errors.is_empty() {
+            if let Some(backups) = self.backups.take() {
+                let _ = backups.remove();
+            }
+        }
+
+        for error in errors.iter() {
+            events.error(format!("rollback: {}", error));
+        }
+        match errors.into_iter().next() {
+            Some(error) => Err(format!("rollback failed, {}", error).into()),
+            None => Ok(()),
+        }
+    }
+}
+
+fn staged_path(file_path: &str) -> String {
+    format!("{}.renaming", file_path)
+}
+
+fn moving_path(file_path: &str) -> String {
+    format!("{}.moving", file_path)
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use crate::{backup::BACKUPS_DIR, rename::FileMove};
+
+    use super::Transaction;
+
+    #[test]
+    fn test_rollback() {
+        let dir = std::env::temp_dir().join(format!("fqn-renamer-tx-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| -> String { dir.join(name).to_str().unwrap().to_owned() };
+        std::fs::write(path("A.java"), "class A {}\n").unwrap();
+        std::fs::write(path("B.java"), "class B {}\n").unwrap();
+
+        let mut transaction = Transaction::new(dir.to_str().unwrap());
+        transaction.stage(&path("A.java"), b"class C {}\n").unwrap();
+        transaction.commit().unwrap();
+        let moves = [
+            FileMove {
+                from: path("A.java"),
+                to: path("C.java"),
+            },
+            // fails, nothing to move
+            FileMove {
+                from: path("Missing.java"),
+                to: path("D.java"),
+            },
+        ];
+        assert!(transaction
+            .move_files(&moves, &mut Default::default())
+            .is_err());
+        assert_eq!(
+            "class C {}\n",
+            std::fs::read_to_string(path("A.java.moving")).unwrap()
+        );
+
+        transaction.rollback(&mut Default::default()).unwrap();
+        let mut files = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into())
+            .collect::<Vec<PathBuf>>();
+        files.sort();
+        assert_eq!(
+            vec![
+                PathBuf::from(".fqn-renamer"),
+                PathBuf::from("A.java"),
+                PathBuf::from("B.java")
+            ],
+            files
+        );
+        // the backup set is gone with the rename
+        assert!(std::fs::read_dir(dir.join(BACKUPS_DIR))
+            .unwrap()
+            .next()
+            .is_none());
+        assert_eq!(
+            "class A {}\n",
+            std::fs::read_to_string(path("A.java")).unwrap()
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rollback_removes_created_dirs() {
+        let dir = std::env::temp_dir().join(format!("fqn-renamer-tx-dirs-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("net")).unwrap();
+        let path = |name: &str| -> String { dir.join(name).to_str().unwrap().to_owned() };
+        std::fs::write(path("A.java"), "class A {}\n").unwrap();
+        std::fs::write(path("B.java"), "class B {}\n").unwrap();
+        std::fs::write(path("readme"), "").unwrap();
+
+        let mut transaction = Transaction::new(dir.to_str().unwrap());
+        let moves = [
+            FileMove {
+                from: path("A.java"),
+                to: path("net/baz/qux/A.java"),
+            },
+            // fails, its directory would be a file
+            FileMove {
+                from: path("B.java"),
+                to: path("readme/B.java"),
+            },
+        ];
+        assert!(transaction
+            .move_files(&moves, &mut Default::default())
+            .is_err());
+        assert!(dir.join("net/baz/qux/A.java").exists());
+
+        transaction.rollback(&mut Default::default()).unwrap();
+        let mut files = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into())
+            .collect::<Vec<PathBuf>>();
+        files.sort();
+        assert_eq!(
+            vec![
+                PathBuf::from("A.java"),
+                PathBuf::from("B.java"),
+                PathBuf::from("net"),
+                PathBuf::from("readme")
+            ],
+            files
+        );
+        // it was there before
+        assert!(std::fs::read_dir(dir.join("net")).unwrap().next().is_none());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_commit_nothing() {
+        let mut transaction = Transaction::new(".");
+        transaction.commit().unwrap();
+        transaction.finish().unwrap();
+        assert!(transaction.backups.is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_stage_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("fqn-renamer-mode-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o750)).unwrap();
+
+        let mut transaction = Transaction::new(".");
+        transaction.stage(path, b"#!/bin/bash\n").unwrap();
+        let staged = std::fs::metadata(format!("{}.renaming", path)).unwrap();
+        assert_eq!(0o750, staged.permissions().mode() & 0o777);
+
+        transaction.rollback(&mut Default::default()).unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,9 +1,11 @@
-use crate::{app::App, event_log};
-
-use crate::fqcn::Fqcn;
-use crate::matched_file::MatchedFile;
+use crate::app::App;
 use crate::scrollable::Scrollable;
 
+use fqn_renamer::event_log;
+use fqn_renamer::fqcn::Fqcn;
+use fqn_renamer::matched_file::{LineKind, MatchedFile};
+use fqn_renamer::package::Package;
+
 use std::cell::RefCell;
 use tui::{
     backend::Backend,
@@ -15,6 +17,13 @@ use tui::{
 };
 
 pub fn draw<B: Backend>(f: &mut Frame<B>, app: &mut App) {
+    const MAX_QUEUE_LINES: usize = 5;
+    let queue_height = if app.batch.is_empty() {
+        0
+    } else {
+        app.batch.len().min(MAX_QUEUE_LINES) as u16 + 2
+    };
+
     let layout = Layout::default()
         .horizontal_margin(10)
         .vertical_margin(2)
@@ -22,6 +31,8 @@ pub fn draw<B: Backend>(f: &mut Frame<B>, app: &mut App) {
             [
                 // inputs
                 Constraint::Length(9),
+                // batch queue
+                Constraint::Length(queue_height),
                 // results
                 Constraint::Min(10),
                 // event log
@@ -74,7 +85,7 @@ pub fn draw<B: Backend>(f: &mut Frame<B>, app: &mut App) {
             .block(default_block().title("Search").borders(Borders::ALL))
             .focused_style(focused_style())
             .styler(make_fqcn_styler())
-            .placeholder_text("Identifier or FQCN");
+            .placeholder_text("Identifier, FQCN or package");
 
         f.render_interactive(search_input, l[0], &app.inputs.search_for_ident);
 
@@ -97,7 +108,7 @@ pub fn draw<B: Backend>(f: &mut Frame<B>, app: &mut App) {
             .focused_style(focused_style())
             .block(default_block().title("Replace").borders(Borders::ALL))
             .styler(make_fqcn_styler())
-            .placeholder_text("Identifier or FQCN");
+            .placeholder_text("Identifier, FQCN or package");
 
         f.render_interactive(search_input, l[0], &app.inputs.replace_with_ident);
 
@@ -109,12 +120,54 @@ pub fn draw<B: Backend>(f: &mut Frame<B>, app: &mut App) {
         f.render_interactive(replace_button, l[1], &app.inputs.replace_button)
     }
 
+    // Batch queue; every pair is searched for at once
+    if !app.batch.is_empty() {
+        let items = app
+            .batch
+            .iter()
+            .enumerate()
+            .take(MAX_QUEUE_LINES)
+            .map(|(idx, mapping)| {
+                let status = match app.batch_references.get(idx) {
+                    Some(&num_files) if num_files > 0 => Span::styled(
+                        format!("{:>5} files  ", num_files),
+                        Style::default().fg(Color::Green),
+                    ),
+                    Some(_) => Span::styled(
+                        format!("{:>11}  ", "unused"),
+                        Style::default().fg(Color::DarkGray),
+                    ),
+                    None if app.is_searching() => Span::styled(
+                        format!("{:>11}  ", "searching"),
+                        Style::default().fg(Color::Yellow),
+                    ),
+                    None => Span::raw(format!("{:>13}", "")),
+                };
+
+                ListItem::new(Spans::from(vec![
+                    status,
+                    Span::raw(mapping.find.as_str()),
+                    Span::raw(" -> "),
+                    Span::raw(mapping.replace.as_str()),
+                ]))
+            })
+            .collect::<Vec<_>>();
+
+        let num_referenced = app.batch_references.iter().filter(|&&n| n > 0).count();
+        let queue = List::new(items).block(default_block().title(format!(
+            "Batch ({}/{} referenced)",
+            num_referenced,
+            app.batch.len()
+        )));
+        f.render_widget(queue, layout[1]);
+    }
+
     // Results / Replacement Preview area
     {
         let l = Layout::default()
             .direction(Direction::Horizontal)
             .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
-            .split(layout[1]);
+            .split(layout[2]);
         let search_results_l = l[0];
         let replace_review_l = l[1];
 
@@ -133,20 +186,35 @@ pub fn draw<B: Backend>(f: &mut Frame<B>, app: &mut App) {
                 search_scrollable.borrow_mut().push(|| Spans::from(vec![]));
             }
             first = false;
-            add_match_to_scrollable(&mut search_scrollable, found_match, true);
+            add_match_to_scrollable(&mut search_scrollable, found_match, None, true);
+        }
+
+        let mut title = vec![
+            Span::raw("Search Results "),
+            Span::raw(format!("({} files, {} matches)", num_files, num_matches)),
+        ];
+        if let Some(stats) = &app.search_stats {
+            title.push(Span::styled(
+                format!(" {}", stats),
+                Style::default().fg(Color::DarkGray),
+            ));
+        }
+        if let Some(error) = app.search_errors.last() {
+            title.push(Span::styled(
+                format!(" {} errors, last: {}", app.search_errors.len(), error),
+                Style::default().fg(Color::Red),
+            ));
         }
 
         let search_results = Paragraph::new(Text::from(search_scrollable.take().get())).block(
             Block::default()
-                .title(Spans::from(vec![
-                    Span::raw("Search Results "),
-                    Span::raw(format!("({} files, {} matches)", num_files, num_matches)),
-                ]))
+                .title(Spans::from(title))
                 .borders(Borders::ALL),
         );
         f.render_widget(search_results, search_results_l);
 
-        let replacements = &app.replacments;
+        let plan = &app.plan;
+        let replacements = &plan.replacements;
         let mut preview_scrollable = RefCell::new(Scrollable::new(
             app.results_scroll_offset,
             search_results_l.height as usize,
@@ -158,7 +226,8 @@ pub fn draw<B: Backend>(f: &mut Frame<B>, app: &mut App) {
                 preview_scrollable.borrow_mut().push(|| Spans::from(vec![]));
             }
             first = false;
-            add_match_to_scrollable(&mut preview_scrollable, found_match, false);
+            let moved_to = plan.moved_to(found_match.file_path());
+            add_match_to_scrollable(&mut preview_scrollable, found_match, moved_to, false);
         }
 
         let replace_preview_b = Paragraph::new(Text::from(preview_scrollable.take().get())).block(
@@ -196,15 +265,16 @@ pub fn draw<B: Backend>(f: &mut Frame<B>, app: &mut App) {
                 .collect::<Vec<_>>(),
         )
         .block(event_block);
-        f.render_widget(events, layout[2]);
+        f.render_widget(events, layout[3]);
     } else {
-        f.render_widget(event_block, layout[2]);
+        f.render_widget(event_block, layout[3]);
     }
 }
 
 fn add_match_to_scrollable<'a>(
     scrollable: &mut RefCell<Scrollable<Spans<'a>>>,
     found_match: &'a MatchedFile,
+    moved_to: Option<&'a str>,
     is_preview: bool,
 ) {
     let section_sep = format!("    |{}", "-".repeat(10));
@@ -220,6 +290,14 @@ fn add_match_to_scrollable<'a>(
             Style::default().fg(tui::style::Color::Magenta),
         )];
 
+        if let Some(moved_to) = moved_to {
+            v.push(Span::raw(" -> "));
+            v.push(Span::styled(
+                moved_to,
+                Style::default().fg(tui::style::Color::Magenta),
+            ));
+        }
+
         if is_preview {
             v.push(Span::raw(" "));
             v.push(Span::styled(
@@ -231,29 +309,50 @@ fn add_match_to_scrollable<'a>(
         v.into()
     });
 
-    let mut prev_line = None;
+    let mut next_line = None;
 
     for line in found_match.lines() {
         let line_num = line.num();
+        let kind = line.kind();
 
-        if let Some(prev) = prev_line {
-            if prev + 1 != line_num {
+        if let Some(next) = next_line {
+            if next != line_num {
                 scrollable
                     .borrow_mut()
                     .push(|| Spans::from(vec![Span::raw(section_sep.clone())]));
             }
         }
-        prev_line = Some(line_num);
+        // inserted lines go in front of `line_num`, rather than replacing it
+        next_line = match kind {
+            LineKind::Replace | LineKind::Delete => Some(line_num + 1),
+            LineKind::Insert => Some(line_num),
+        };
 
         scrollable.borrow_mut().push(|| {
-            let line_num_prefix = std::iter::once(Span::styled(
-                // add one to make line numbers one-indexed
-                format!("{:>4}| ", line_num + 1),
-                Style::default().fg(Color::DarkGray),
-            ));
+            let line_num_prefix = std::iter::once(match kind {
+                LineKind::Replace => Span::styled(
+                    // add one to make line numbers one-indexed
+                    format!("{:>4}| ", line_num + 1),
+                    Style::default().fg(Color::DarkGray),
+                ),
+                LineKind::Insert => {
+                    Span::styled(format!("{:>4}| ", "+"), Style::default().fg(Color::Green))
+                }
+                LineKind::Delete => Span::styled(
+                    format!("{:>4}| ", line_num + 1),
+                    Style::default().fg(Color::Red),
+                ),
+            });
 
             let highlighted = line.iter().map(|(is_match, substr)| {
-                if is_match {
+                if kind == LineKind::Delete {
+                    Span::styled(
+                        substr,
+                        Style::default()
+                            .fg(Color::Red)
+                            .add_modifier(Modifier::CROSSED_OUT),
+                    )
+                } else if is_match {
                     Span::styled(substr, Style::default().fg(match_color))
                 } else {
                     Span::raw(substr)
@@ -276,9 +375,14 @@ fn make_fqcn_styler() -> impl FnOnce(bool, &str) -> Spans {
                     fqcn.package_with_trailing().to_owned(),
                     Style::default().fg(Color::Green),
                 ),
-                Span::styled(fqcn.ident().to_owned(), Style::default().fg(Color::Blue)),
+                Span::styled(
+                    fqcn.class_name().to_owned(),
+                    Style::default().fg(Color::Blue),
+                ),
             ]
             .into()
+        } else if Package::new(contents).is_some() {
+            Span::styled(contents.to_owned(), Style::default().fg(Color::Green)).into()
         } else {
             Span::raw(contents).into()
         }